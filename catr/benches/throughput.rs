@@ -0,0 +1,91 @@
+//! Throughput of plain concatenation against the line-by-line loop.
+//!
+//! Writes a file of `CATR_BENCH_MB` MiB (2048 by default) and copies it
+//! with `catr FILE`, which takes the fast path, and with `catr -s FILE`,
+//! which goes through the line loop but writes the same bytes as the file
+//! has no blank lines. Each is timed into a file and into a pipe.
+//!
+//!     cargo bench --bench throughput
+//!     CATR_BENCH_MB=256 cargo bench --bench throughput
+
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+const CATR: &str = env!("CARGO_BIN_EXE_catr");
+
+fn main() {
+    let mb: u64 = std::env::var("CATR_BENCH_MB")
+        .ok()
+        .and_then(|mb| mb.parse().ok())
+        .unwrap_or(2048);
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("input.txt");
+    let size = write_input(&input, mb << 20);
+    println!("input: {} MiB", size >> 20);
+
+    for (name, args) in [("fast path", &[][..]), ("line loop", &["-s"][..])] {
+        let output = dir.path().join("output.txt");
+        let elapsed = to_file(args, &input, &output);
+        assert_eq!(output.metadata().unwrap().len(), size);
+        report(name, "file", size, elapsed);
+
+        let (copied, elapsed) = to_pipe(args, &input);
+        assert_eq!(copied, size);
+        report(name, "pipe", size, elapsed);
+    }
+}
+
+/// Fills `path` with at least `size` bytes of text lines, none of them blank.
+fn write_input(path: &Path, size: u64) -> u64 {
+    let mut out = BufWriter::new(File::create(path).unwrap());
+    let mut written = 0;
+    let mut i = 0u64;
+    while written < size {
+        let line = format!("{:>10} the quick brown fox jumps over the lazy dog {:x}\n", i, i.wrapping_mul(0x9e37_79b9));
+        out.write_all(line.as_bytes()).unwrap();
+        written += line.len() as u64;
+        i += 1;
+    }
+    out.flush().unwrap();
+    written
+}
+
+fn to_file(args: &[&str], input: &Path, output: &Path) -> Duration {
+    let start = Instant::now();
+    let status = Command::new(CATR)
+        .args(args)
+        .arg(input)
+        .stdout(File::create(output).unwrap())
+        .status()
+        .unwrap();
+    assert!(status.success());
+    start.elapsed()
+}
+
+fn to_pipe(args: &[&str], input: &Path) -> (u64, Duration) {
+    let start = Instant::now();
+    let mut child = Command::new(CATR).args(args).arg(input).stdout(Stdio::piped()).spawn().unwrap();
+    let mut stdout = child.stdout.take().unwrap();
+    let mut buf = vec![0; 1 << 20];
+    let mut copied = 0;
+    loop {
+        match stdout.read(&mut buf).unwrap() {
+            0 => break,
+            n => copied += n as u64,
+        }
+    }
+    assert!(child.wait().unwrap().success());
+    (copied, start.elapsed())
+}
+
+fn report(name: &str, sink: &str, size: u64, elapsed: Duration) {
+    let mib = size as f64 / (1 << 20) as f64;
+    println!(
+        "{name:<10} -> {sink:<5} {:>8.3} s {:>10.1} MiB/s",
+        elapsed.as_secs_f64(),
+        mib / elapsed.as_secs_f64()
+    );
+}
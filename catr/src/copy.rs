@@ -0,0 +1,182 @@
+//! Plain concatenation, for when no option changes what is written.
+//!
+//! On Linux the kernel moves the bytes from one file descriptor to the
+//! other without copying them through catr: `copy_file_range` between
+//! regular files, `sendfile` from a regular file to anything else, and
+//! `splice` when either side is a pipe. Whatever the kernel refuses falls
+//! back to reading and writing through a large buffer.
+
+use std::io::{self, Read, Write};
+
+/// Size of the buffer for the read/write fallback.
+const BUF_SIZE: usize = 256 * 1024;
+
+/// What `copy` needs of its input and output besides `Read` and `Write`: a
+/// file descriptor where the kernel can do the copying.
+#[cfg(target_os = "linux")]
+pub(crate) trait Fd: std::os::fd::AsFd {}
+#[cfg(target_os = "linux")]
+impl<T: std::os::fd::AsFd> Fd for T {}
+#[cfg(not(target_os = "linux"))]
+pub(crate) trait Fd {}
+#[cfg(not(target_os = "linux"))]
+impl<T> Fd for T {}
+
+/// Copies everything left in `input` to `out`, returning the number of bytes.
+pub(crate) fn copy<R: Read + Fd, W: Write + Fd>(input: &mut R, out: &mut W) -> io::Result<u64> {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::fd::AsRawFd;
+
+        // nothing written through `out` may still be waiting in its buffer
+        out.flush()?;
+        let (fd_in, fd_out) = (input.as_fd().as_raw_fd(), out.as_fd().as_raw_fd());
+        let mut copied = 0;
+        for syscall in [linux::Syscall::CopyFileRange, linux::Syscall::Sendfile, linux::Syscall::Splice] {
+            if linux::zero_copy(syscall, fd_in, fd_out, &mut copied)? {
+                return Ok(copied);
+            }
+        }
+        Ok(copied + buffered(input, out)?)
+    }
+    #[cfg(not(target_os = "linux"))]
+    buffered(input, out)
+}
+
+/// Copies `input` to `out` through a buffer of `BUF_SIZE` bytes.
+pub(crate) fn buffered<R: Read, W: Write>(input: &mut R, out: &mut W) -> io::Result<u64> {
+    let mut buf = vec![0; BUF_SIZE];
+    let mut copied = 0;
+    loop {
+        let n = match input.read(&mut buf) {
+            Ok(0) => break, // reached EOF
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        out.write_all(&buf[..n])?;
+        copied += n as u64;
+    }
+    out.flush()?;
+    Ok(copied)
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::io;
+    use std::os::fd::RawFd;
+    use std::ptr;
+
+    /// Most bytes to ask for in one call.
+    const CHUNK: usize = 1 << 30;
+
+    #[derive(Debug, Clone, Copy)]
+    pub(super) enum Syscall {
+        CopyFileRange,
+        Sendfile,
+        Splice,
+    }
+
+    /// Copies from `fd_in` to `fd_out` with `syscall` until EOF, adding the
+    /// bytes to `copied`. Returns `false` if `syscall` cannot copy between
+    /// these descriptors, which may be after some bytes went through; both
+    /// calls use the file offsets, so another way can carry on from there.
+    pub(super) fn zero_copy(syscall: Syscall, fd_in: RawFd, fd_out: RawFd, copied: &mut u64) -> io::Result<bool> {
+        let mut first = true;
+        loop {
+            // SAFETY: plain descriptors and no offset pointers, so the
+            // kernel only touches the two files
+            let n = unsafe {
+                match syscall {
+                    Syscall::CopyFileRange => {
+                        libc::copy_file_range(fd_in, ptr::null_mut(), fd_out, ptr::null_mut(), CHUNK, 0)
+                    }
+                    Syscall::Sendfile => libc::sendfile(fd_out, fd_in, ptr::null_mut(), CHUNK),
+                    Syscall::Splice => libc::splice(
+                        fd_in,
+                        ptr::null_mut(),
+                        fd_out,
+                        ptr::null_mut(),
+                        CHUNK,
+                        libc::SPLICE_F_MOVE,
+                    ),
+                }
+            };
+            match n {
+                // files in /proc and /sys report EOF here while a read
+                // would still return data, so let the next way make sure
+                0 => return Ok(!first),
+                n if n > 0 => *copied += n as u64,
+                _ => {
+                    let e = io::Error::last_os_error();
+                    match e.raw_os_error() {
+                        Some(libc::EINTR) => continue,
+                        Some(
+                            libc::EINVAL
+                            | libc::ENOSYS
+                            | libc::EXDEV
+                            | libc::EBADF
+                            | libc::EOPNOTSUPP
+                            | libc::EPERM
+                            | libc::ESPIPE,
+                        ) => return Ok(false),
+                        _ => return Err(e),
+                    }
+                }
+            }
+            first = false;
+        }
+    }
+}
+
+// -------------------- tests --------------------
+#[cfg(test)]
+mod tests {
+    use super::{buffered, copy, BUF_SIZE};
+    use std::fs::{self, File};
+    use std::io::{Seek, SeekFrom};
+
+    #[test]
+    fn test_copy() {
+        let dir = tempfile::tempdir().unwrap();
+        let data: Vec<u8> = (0..3 * BUF_SIZE + 7).map(|i| (i % 251) as u8).collect();
+        let src = dir.path().join("src");
+        fs::write(&src, &data).unwrap();
+
+        // appends to what is already there, and starts where `input` is
+        let dst = dir.path().join("dst");
+        fs::write(&dst, b"head").unwrap();
+        let mut input = File::open(&src).unwrap();
+        input.seek(SeekFrom::Start(7)).unwrap();
+        let mut out = fs::OpenOptions::new().write(true).open(&dst).unwrap();
+        out.seek(SeekFrom::End(0)).unwrap();
+        assert_eq!(copy(&mut input, &mut out).unwrap(), data.len() as u64 - 7);
+        assert_eq!(fs::read(&dst).unwrap(), [b"head", &data[7..]].concat());
+
+        // an appending output is not something copy_file_range can do
+        let mut input = File::open(&src).unwrap();
+        let mut out = fs::OpenOptions::new().append(true).open(&dst).unwrap();
+        assert_eq!(copy(&mut input, &mut out).unwrap(), data.len() as u64);
+        assert_eq!(fs::read(&dst).unwrap(), [b"head", &data[7..], &data].concat());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_copy_proc() {
+        // copy_file_range and sendfile see no data in /proc
+        let dir = tempfile::tempdir().unwrap();
+        let dst = dir.path().join("dst");
+        let mut out = File::create(&dst).unwrap();
+        let copied = copy(&mut File::open("/proc/self/status").unwrap(), &mut out).unwrap();
+        assert!(copied > 0);
+        assert!(fs::read_to_string(&dst).unwrap().starts_with("Name:"));
+    }
+
+    #[test]
+    fn test_buffered() {
+        let data: Vec<u8> = (0..BUF_SIZE + 1).map(|i| (i % 7) as u8).collect();
+        let mut out = Vec::new();
+        assert_eq!(buffered(&mut data.as_slice(), &mut out).unwrap(), data.len() as u64);
+        assert_eq!(out, data);
+    }
+}
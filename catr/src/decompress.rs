@@ -0,0 +1,243 @@
+//! Transparent decompression of gzip, bzip2, xz and zstd input, each behind
+//! the cargo feature of the same name. A compressed file is told by its
+//! first bytes, not by its name, so `app.log.3.gz` and a compressed stream
+//! on stdin are both read as text; `--no-decompress` turns this off. Text that
+//! only starts like a compressed file is written as it is.
+
+use std::cell::RefCell;
+use std::io::{self, Cursor, Read};
+use std::rc::Rc;
+
+/// Longest magic number, that of bzip2 with the magic of its first block.
+const MAGIC_LEN: usize = 10;
+
+/// The magic numbers, byte by byte: every byte of a header has to be one
+/// of those given for its place. A bzip2 stream has its first block, or
+/// its end if it is empty, right after the block size.
+const MAGIC: &[(Codec, &[&[u8]])] = &[
+    #[cfg(feature = "gzip")]
+    (Codec::Gzip, &[b"\x1f", b"\x8b", b"\x08"]),
+    #[cfg(feature = "bzip2")]
+    (Codec::Bzip2, &[b"B", b"Z", b"h", b"123456789", b"1", b"A", b"Y", b"&", b"S", b"Y"]),
+    #[cfg(feature = "bzip2")]
+    (Codec::Bzip2, &[b"B", b"Z", b"h", b"123456789", b"\x17", b"r", b"E", b"8", b"P", b"\x90"]),
+    #[cfg(feature = "xz")]
+    (Codec::Xz, &[b"\xfd", b"7", b"z", b"X", b"Z", b"\x00"]),
+    #[cfg(feature = "zstd")]
+    (Codec::Zstd, &[b"\x28", b"\xb5", b"\x2f", b"\xfd"]),
+];
+
+/// A compression format whose decoder is built in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Codec {
+    #[cfg(feature = "gzip")]
+    Gzip,
+    #[cfg(feature = "bzip2")]
+    Bzip2,
+    #[cfg(feature = "xz")]
+    Xz,
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+/// Whether the bytes of `header` all fit `magic`, as far as either goes.
+fn fits(magic: &[&[u8]], header: &[u8]) -> bool {
+    header.iter().zip(magic).all(|(byte, bytes)| bytes.contains(byte))
+}
+
+/// What `Codec::decode` made of its input.
+pub(crate) enum Decoded<'a, R> {
+    /// the decoded input
+    Text(Box<dyn Read + 'a>),
+    /// input the decoder did not take after all: these bytes, then the
+    /// rest of `R`, as they are
+    Raw(Vec<u8>, R),
+}
+
+impl Codec {
+    /// The format starting with `header`, if it is one catr can decode.
+    pub(crate) fn detect(header: &[u8]) -> Option<Self> {
+        MAGIC
+            .iter()
+            .find(|(_, magic)| header.len() >= magic.len() && fits(magic, header))
+            .map(|&(codec, _)| codec)
+    }
+
+    /// Decodes `input`, which starts with `header`. If the decoder fails on
+    /// its very first read, the input was text that only looked compressed,
+    /// and all of it is handed back unread.
+    pub(crate) fn decode<'a, R: Read + 'a>(self, header: Vec<u8>, input: R) -> io::Result<Decoded<'a, R>> {
+        let shared = Rc::new(RefCell::new(Recorded { inner: input, log: header.clone(), recording: true }));
+        let recorder = Recorder(Rc::clone(&shared));
+        let mut decoder = self.decoder(Cursor::new(header).chain(recorder))?;
+
+        let mut first = vec![0; 8 * 1024];
+        let read = loop {
+            match decoder.read(&mut first) {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                read => break read,
+            }
+        };
+        match read {
+            Ok(n) => {
+                first.truncate(n);
+                let mut shared = shared.borrow_mut();
+                shared.recording = false;
+                shared.log = Vec::new();
+                drop(shared);
+                Ok(Decoded::Text(Box::new(Cursor::new(first).chain(decoder))))
+            }
+            Err(_) => {
+                drop(decoder);
+                let recorded = Rc::into_inner(shared).expect("the decoder is gone").into_inner();
+                Ok(Decoded::Raw(recorded.log, recorded.inner))
+            }
+        }
+    }
+
+    /// Decodes `input`. Concatenated streams, such as `cat a.gz b.gz`,
+    /// decode to the concatenated contents.
+    #[cfg_attr(
+        not(any(feature = "gzip", feature = "bzip2", feature = "xz", feature = "zstd")),
+        allow(unused_variables, unreachable_code)
+    )]
+    pub(crate) fn decoder<'a>(self, input: impl Read + 'a) -> io::Result<Box<dyn Read + 'a>> {
+        Ok(match self {
+            #[cfg(feature = "gzip")]
+            Codec::Gzip => Box::new(flate2::read::MultiGzDecoder::new(input)),
+            #[cfg(feature = "bzip2")]
+            Codec::Bzip2 => Box::new(bzip2::read::MultiBzDecoder::new(input)),
+            #[cfg(feature = "xz")]
+            Codec::Xz => Box::new(xz2::read::XzDecoder::new_multi_decoder(input)),
+            #[cfg(feature = "zstd")]
+            Codec::Zstd => Box::new(zstd::stream::read::Decoder::new(input)?),
+        })
+    }
+}
+
+/// Reads the first bytes of `input`: what the first read brings, and more
+/// only while they may still be the start of a magic number, so that text
+/// from a pipe is not held up. They have to be put back in front of the
+/// rest of `input`.
+pub(crate) fn sniff(input: &mut impl Read) -> io::Result<(Vec<u8>, Option<Codec>)> {
+    let mut header = Vec::with_capacity(MAGIC_LEN);
+    let mut buf = [0; MAGIC_LEN];
+    loop {
+        let n = match input.read(&mut buf[..MAGIC_LEN - header.len()]) {
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        header.extend_from_slice(&buf[..n]);
+        let codec = Codec::detect(&header);
+        let partial = MAGIC.iter().any(|(_, magic)| header.len() < magic.len() && fits(magic, &header));
+        if n == 0 || codec.is_some() || !partial {
+            return Ok((header, codec));
+        }
+    }
+}
+
+/// The input of a decoder, kept while the decoder may yet fail on it.
+struct Recorded<R> {
+    inner: R,
+    /// the bytes read so far, header included
+    log: Vec<u8>,
+    recording: bool,
+}
+
+/// Reads the input of a decoder for it and records what it reads.
+struct Recorder<R>(Rc<RefCell<Recorded<R>>>);
+
+impl<R: Read> Read for Recorder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut recorded = self.0.borrow_mut();
+        let n = recorded.inner.read(buf)?;
+        if recorded.recording {
+            recorded.log.extend_from_slice(&buf[..n]);
+        }
+        Ok(n)
+    }
+}
+
+// -------------------- tests --------------------
+#[cfg(test)]
+mod tests {
+    use super::{sniff, Codec};
+    use std::io::Read;
+
+    #[test]
+    fn test_sniff() {
+        let (header, codec) = sniff(&mut &b"plain text, no magic"[..]).unwrap();
+        assert_eq!((header.as_slice(), codec), (&b"plain text"[..], None));
+        let (header, codec) = sniff(&mut &b"BZ"[..]).unwrap();
+        assert_eq!((header.as_slice(), codec), (&b"BZ"[..], None));
+        // one read is enough for text, the rest may not be there yet
+        let (header, codec) = sniff(&mut (&b"hi\n"[..]).chain(&b"there\n"[..])).unwrap();
+        assert_eq!((header.as_slice(), codec), (&b"hi\n"[..], None));
+        #[cfg(feature = "bzip2")]
+        {
+            let (header, codec) = sniff(&mut (&b"BZ"[..]).chain(&b"h91AY&SY..."[..])).unwrap();
+            assert_eq!((header.as_slice(), codec), (&b"BZh91AY&SY"[..], Some(Codec::Bzip2)));
+        }
+    }
+
+    #[cfg(all(feature = "gzip", feature = "bzip2", feature = "xz", feature = "zstd"))]
+    #[test]
+    fn test_decoder() {
+        use std::fs;
+        use std::io::Cursor;
+
+        let decode = |path: &str| {
+            let mut input = fs::File::open(path).unwrap();
+            let (header, codec) = sniff(&mut input).unwrap();
+            let mut text = String::new();
+            let mut decoder = codec.unwrap().decoder(Cursor::new(header).chain(input)).unwrap();
+            decoder.read_to_string(&mut text).unwrap();
+            text
+        };
+        let fox = fs::read_to_string("tests/inputs/fox.txt").unwrap();
+        for ext in ["gz", "bz2", "xz", "zst"] {
+            assert_eq!(decode(&format!("tests/inputs/fox.txt.{ext}")), fox, "{ext}");
+        }
+        // every member of a concatenated gzip file
+        let spiders = fs::read_to_string("tests/inputs/spiders.txt").unwrap();
+        assert_eq!(decode("tests/inputs/fox-spiders.txt.gz"), fox + &spiders);
+    }
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(Codec::detect(b""), None);
+        assert_eq!(Codec::detect(b"\x1f"), None);
+        assert_eq!(Codec::detect(b"\x1f\x8b\x09"), None);
+        assert_eq!(Codec::detect(b"BZh is a nice word"), None);
+        assert_eq!(Codec::detect(b"BZh91AY&S"), None);
+        #[cfg(feature = "gzip")]
+        assert_eq!(Codec::detect(b"\x1f\x8b\x08"), Some(Codec::Gzip));
+        #[cfg(feature = "bzip2")]
+        assert_eq!(Codec::detect(b"BZh91AY&SY"), Some(Codec::Bzip2));
+        #[cfg(feature = "bzip2")]
+        assert_eq!(Codec::detect(b"BZh1\x17rE8P\x90"), Some(Codec::Bzip2));
+        #[cfg(feature = "xz")]
+        assert_eq!(Codec::detect(b"\xfd7zXZ\x00\x00"), Some(Codec::Xz));
+        #[cfg(feature = "zstd")]
+        assert_eq!(Codec::detect(b"\x28\xb5\x2f\xfd\x24"), Some(Codec::Zstd));
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn test_decode_gives_up() {
+        use super::Decoded;
+
+        let text = b"\x1f\x8b\x08 is how gzip starts\n";
+        let mut input = &text[..];
+        let (header, codec) = sniff(&mut input).unwrap();
+        match codec.unwrap().decode(header, input).unwrap() {
+            Decoded::Raw(raw, mut rest) => {
+                let mut all = raw;
+                rest.read_to_end(&mut all).unwrap();
+                assert_eq!(all, text);
+            }
+            Decoded::Text(_) => panic!("decoded text"),
+        }
+    }
+}
@@ -0,0 +1,356 @@
+//! Character encodings for `--from-encoding`, `--to-encoding` and
+//! `--on-error`.
+//!
+//! Input is decoded to UTF-8 as it is read, before anything looks at its
+//! lines, so lines, blank lines and patterns are those of the text and not
+//! of its bytes, which matters for UTF-16. Output, numbers and all, is
+//! encoded on its way out.
+
+use encoding_rs::{DecoderResult, EncoderResult, Encoding, UTF_16BE, UTF_16LE, UTF_8};
+use std::io::{self, Cursor, Read, Write};
+
+/// How much input `--from-encoding=auto` looks at.
+const SAMPLE_LEN: usize = 64 * 1024;
+/// Size of the chunks that are read and decoded.
+const CHUNK_LEN: usize = 16 * 1024;
+
+/// The encodings to read and write.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transcode {
+    /// `None` to tell from a BOM, or failing that from the input itself
+    pub from: Option<&'static Encoding>,
+    pub to: &'static Encoding,
+    pub on_error: OnError,
+}
+
+/// What to do with input that is not valid in its encoding, and with
+/// characters the output encoding does not have.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OnError {
+    /// write U+FFFD for bad input, `?` for what cannot be written
+    Replace,
+    /// stop with an error
+    Fail,
+    /// leave it out
+    Skip,
+}
+
+impl OnError {
+    pub fn parse(on_error: &str) -> Self {
+        match on_error {
+            "fail" => OnError::Fail,
+            "skip" => OnError::Skip,
+            _ => OnError::Replace,
+        }
+    }
+}
+
+/// The encoding called `label`, like `utf-16le`, `shift_jis` or `latin1`.
+pub fn parse_encoding(label: &str) -> Result<&'static Encoding, String> {
+    Encoding::for_label(label.as_bytes()).ok_or_else(|| format!("unknown encoding -- {}", label))
+}
+
+/// The encoding of text starting with `sample`, which is all of it if it
+/// is shorter than `SAMPLE_LEN`: the one its BOM names, UTF-16 if every
+/// other byte is mostly NUL, else the best guess of chardetng.
+pub fn detect(sample: &[u8]) -> &'static Encoding {
+    if let Some((encoding, _)) = Encoding::for_bom(sample) {
+        return encoding;
+    }
+
+    // ASCII text in UTF-16 has a NUL in every other byte
+    let units = sample.len() / 2;
+    let nul_at = |parity: usize| sample.iter().skip(parity).step_by(2).filter(|&&b| b == 0).count();
+    if units > 0 {
+        if nul_at(1) * 2 > units && nul_at(0) * 8 < units {
+            return UTF_16LE;
+        }
+        if nul_at(0) * 2 > units && nul_at(1) * 8 < units {
+            return UTF_16BE;
+        }
+    }
+
+    let mut detector = chardetng::EncodingDetector::new();
+    detector.feed(sample, sample.len() < SAMPLE_LEN);
+    detector.guess(None, true)
+}
+
+/// `input` decoded to UTF-8.
+pub(crate) struct Decode<R> {
+    name: String,
+    inner: R,
+    encoding: &'static Encoding,
+    decoder: encoding_rs::Decoder,
+    on_error: OnError,
+    raw: Vec<u8>,
+    /// bytes of input decoded so far, to say where it went wrong
+    offset: u64,
+    done: bool,
+    out: Vec<u8>,
+    pos: usize,
+}
+
+impl Decode<Box<dyn Read>> {
+    /// Starts decoding `input`, read from the file `name`.
+    pub(crate) fn new(name: &str, mut input: Box<dyn Read>, transcode: &Transcode) -> io::Result<Self> {
+        let (encoding, decoder, input): (_, _, Box<dyn Read>) = match transcode.from {
+            Some(encoding) => (encoding, encoding.new_decoder_with_bom_removal(), input),
+            None => {
+                let mut sample = Vec::with_capacity(SAMPLE_LEN);
+                input.by_ref().take(SAMPLE_LEN as u64).read_to_end(&mut sample)?;
+                let encoding = detect(&sample);
+                (encoding, encoding.new_decoder(), Box::new(Cursor::new(sample).chain(input)))
+            }
+        };
+        Ok(Decode {
+            name: name.to_string(),
+            inner: input,
+            encoding,
+            decoder,
+            on_error: transcode.on_error,
+            raw: vec![0; CHUNK_LEN],
+            offset: 0,
+            done: false,
+            out: Vec::new(),
+            pos: 0,
+        })
+    }
+}
+
+impl<R: Read> Decode<R> {
+    /// Decodes the next chunk of input into `out`; leaves it empty at EOF.
+    fn refill(&mut self) -> io::Result<()> {
+        self.out.clear();
+        self.pos = 0;
+        while self.out.is_empty() && !self.done {
+            let n = match self.inner.read(&mut self.raw) {
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            let last = n == 0;
+            self.done = last;
+
+            let mut input = &self.raw[..n];
+            loop {
+                let start = self.out.len();
+                let room = self
+                    .decoder
+                    .max_utf8_buffer_length_without_replacement(input.len())
+                    .unwrap_or(3 * input.len() + 16);
+                self.out.resize(start + room, 0);
+                let (result, read, written) =
+                    self.decoder.decode_to_utf8_without_replacement(input, &mut self.out[start..], last);
+                self.out.truncate(start + written);
+                input = &input[read..];
+                self.offset += read as u64;
+                match result {
+                    DecoderResult::InputEmpty => break,
+                    DecoderResult::OutputFull => {}
+                    DecoderResult::Malformed(..) => match self.on_error {
+                        OnError::Replace => self.out.extend_from_slice("\u{fffd}".as_bytes()),
+                        OnError::Skip => {}
+                        OnError::Fail => {
+                            return Err(io::Error::new(
+                                io::ErrorKind::InvalidData,
+                                format!("{}: invalid {} input near byte {}", self.name, self.encoding.name(), self.offset),
+                            ));
+                        }
+                    },
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for Decode<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.out.len() {
+            self.refill()?;
+        }
+        let n = (self.out.len() - self.pos).min(buf.len());
+        buf[..n].copy_from_slice(&self.out[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Encodes the UTF-8 written to it into `to` for `inner`.
+pub(crate) struct Encode<W> {
+    inner: W,
+    to: &'static Encoding,
+    encoder: encoding_rs::Encoder,
+    on_error: OnError,
+    /// the start of a character whose other bytes are still to come
+    partial: Vec<u8>,
+    buf: Vec<u8>,
+}
+
+impl<W: Write> Encode<W> {
+    pub(crate) fn new(inner: W, transcode: &Transcode) -> Self {
+        let to = transcode.to;
+        Encode {
+            inner,
+            to,
+            // UTF-16 has no encoder, see `encode`
+            encoder: to.new_encoder(),
+            on_error: transcode.on_error,
+            partial: Vec::new(),
+            buf: Vec::new(),
+        }
+    }
+
+    /// Encodes `text` into `buf`.
+    fn encode(&mut self, text: &str) -> io::Result<()> {
+        self.buf.clear();
+        if self.to == UTF_8 {
+            self.buf.extend_from_slice(text.as_bytes());
+        } else if self.to == UTF_16LE || self.to == UTF_16BE {
+            for unit in text.encode_utf16() {
+                let bytes = if self.to == UTF_16LE { unit.to_le_bytes() } else { unit.to_be_bytes() };
+                self.buf.extend_from_slice(&bytes);
+            }
+        } else {
+            let mut text = text;
+            loop {
+                let start = self.buf.len();
+                let room = self
+                    .encoder
+                    .max_buffer_length_from_utf8_without_replacement(text.len())
+                    .unwrap_or(4 * text.len() + 16);
+                self.buf.resize(start + room, 0);
+                // each write is complete on its own, which resets stateful
+                // encodings like ISO-2022-JP at its end
+                let (result, read, written) =
+                    self.encoder.encode_from_utf8_without_replacement(text, &mut self.buf[start..], true);
+                self.buf.truncate(start + written);
+                text = &text[read..];
+                match result {
+                    EncoderResult::InputEmpty => break,
+                    EncoderResult::OutputFull => {}
+                    EncoderResult::Unmappable(c) => match self.on_error {
+                        OnError::Replace => self.buf.push(b'?'),
+                        OnError::Skip => {}
+                        OnError::Fail => {
+                            return Err(io::Error::new(
+                                io::ErrorKind::InvalidData,
+                                format!("cannot encode U+{:04X} in {}", c as u32, self.to.name()),
+                            ));
+                        }
+                    },
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for Encode<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.partial.extend_from_slice(buf);
+        let partial = std::mem::take(&mut self.partial);
+        let (text, rest) = match std::str::from_utf8(&partial) {
+            Ok(text) => (text, &[][..]),
+            // an incomplete character at the end waits for the next write
+            Err(e) if e.error_len().is_none() => {
+                let (valid, rest) = partial.split_at(e.valid_up_to());
+                (std::str::from_utf8(valid).unwrap(), rest)
+            }
+            // decoded input is UTF-8, but -v and the like need not be
+            Err(_) => {
+                let text = String::from_utf8_lossy(&partial).into_owned();
+                self.encode(&text)?;
+                self.inner.write_all(&self.buf)?;
+                return Ok(buf.len());
+            }
+        };
+        self.encode(text)?;
+        self.partial = rest.to_vec();
+        self.inner.write_all(&self.buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// -------------------- tests --------------------
+#[cfg(test)]
+mod tests {
+    use super::{detect, parse_encoding, Decode, Encode, OnError, Transcode};
+    use encoding_rs::{Encoding, SHIFT_JIS, UTF_16BE, UTF_16LE, UTF_8, WINDOWS_1252};
+    use std::io::{Cursor, Read, Write};
+
+    fn transcode(from: Option<&'static Encoding>, to: &'static Encoding, on_error: OnError) -> Transcode {
+        Transcode { from, to, on_error }
+    }
+
+    fn decode(input: &[u8], transcode: &Transcode) -> Result<String, String> {
+        let input = Box::new(Cursor::new(input.to_vec()));
+        let mut decode = Decode::new("in", input, transcode).map_err(|e| e.to_string())?;
+        let mut text = String::new();
+        decode.read_to_string(&mut text).map_err(|e| e.to_string())?;
+        Ok(text)
+    }
+
+    fn encode(text: &str, transcode: &Transcode) -> Result<Vec<u8>, String> {
+        let mut out = Vec::new();
+        let mut encode = Encode::new(&mut out, transcode);
+        // split inside a character, as a writer may
+        let (a, b) = text.as_bytes().split_at(text.len() / 2);
+        encode.write_all(a).map_err(|e| e.to_string())?;
+        encode.write_all(b).map_err(|e| e.to_string())?;
+        Ok(out)
+    }
+
+    #[test]
+    fn test_parse_encoding() {
+        assert_eq!(parse_encoding("UTF-16LE"), Ok(UTF_16LE));
+        assert_eq!(parse_encoding("sjis"), Ok(SHIFT_JIS));
+        assert_eq!(parse_encoding("latin1"), Ok(WINDOWS_1252));
+        assert_eq!(parse_encoding("klingon"), Err("unknown encoding -- klingon".to_string()));
+    }
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(detect(b"\xef\xbb\xbfa"), UTF_8);
+        assert_eq!(detect(b"\xff\xfea\x00"), UTF_16LE);
+        assert_eq!(detect(b"a\x00b\x00\n\x00"), UTF_16LE);
+        assert_eq!(detect(b"\x00a\x00b\x00\n"), UTF_16BE);
+        assert_eq!(detect("naïve café\n".as_bytes()), UTF_8);
+    }
+
+    #[test]
+    fn test_decode() {
+        let utf16: Vec<u8> = "\u{feff}a\nö\n".encode_utf16().flat_map(|unit| unit.to_le_bytes()).collect();
+        let auto = transcode(None, UTF_8, OnError::Replace);
+        assert_eq!(decode(&utf16, &auto).unwrap(), "a\nö\n");
+
+        let sjis = transcode(Some(SHIFT_JIS), UTF_8, OnError::Replace);
+        assert_eq!(decode(b"\x93\x8c\x8b\x9e,1\n", &sjis).unwrap(), "東京,1\n");
+
+        let bad = b"a\xffb\n";
+        assert_eq!(decode(bad, &transcode(Some(UTF_8), UTF_8, OnError::Replace)).unwrap(), "a\u{fffd}b\n");
+        assert_eq!(decode(bad, &transcode(Some(UTF_8), UTF_8, OnError::Skip)).unwrap(), "ab\n");
+        assert_eq!(
+            decode(bad, &transcode(Some(UTF_8), UTF_8, OnError::Fail)).unwrap_err(),
+            "in: invalid UTF-8 input near byte 2"
+        );
+    }
+
+    #[test]
+    fn test_encode() {
+        let to = |to, on_error| transcode(None, to, on_error);
+        assert_eq!(encode("aö\n", &to(UTF_16LE, OnError::Fail)).unwrap(), b"a\x00\xf6\x00\n\x00");
+        assert_eq!(encode("aö\n", &to(UTF_16BE, OnError::Fail)).unwrap(), b"\x00a\x00\xf6\x00\n");
+        assert_eq!(encode("東京\n", &to(SHIFT_JIS, OnError::Fail)).unwrap(), b"\x93\x8c\x8b\x9e\n");
+        assert_eq!(encode("ö ✓\n", &to(WINDOWS_1252, OnError::Replace)).unwrap(), b"\xf6 ?\n");
+        assert_eq!(encode("ö ✓\n", &to(WINDOWS_1252, OnError::Skip)).unwrap(), b"\xf6 \n");
+        assert_eq!(
+            encode("ö ✓\n", &to(WINDOWS_1252, OnError::Fail)).unwrap_err(),
+            "cannot encode U+2713 in windows-1252"
+        );
+    }
+}
@@ -0,0 +1,112 @@
+//! Line endings and byte order marks: the conversions of `--crlf-to-lf`,
+//! `--lf-to-crlf`, `--strip-bom` and `--add-bom`, and the `--detect` report.
+
+use std::fmt;
+
+/// The UTF-8 byte order mark.
+pub const BOM: &[u8] = b"\xef\xbb\xbf";
+
+/// The line ending to write. Only lines ending in LF are converted; a lone
+/// CR does not end a line for catr.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Newline {
+    /// `--crlf-to-lf`
+    Lf,
+    /// `--lf-to-crlf`
+    Crlf,
+}
+
+/// What to do with byte order marks.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Bom {
+    /// drop the BOM at the start of every file (`--strip-bom`)
+    Strip,
+    /// drop them, then start the output with one (`--add-bom`)
+    Add,
+}
+
+/// The line endings and BOM of one file, for `--detect`.
+#[derive(Debug, Default, PartialEq)]
+pub struct Report {
+    pub bom: bool,
+    pub lf: u64,
+    pub crlf: u64,
+    pub cr: u64,
+    /// whether a line was seen yet, and so whether a BOM can still come
+    started: bool,
+}
+
+impl Report {
+    /// Takes in the next line of the file, which ends in LF unless it is
+    /// the last one.
+    pub fn add_line(&mut self, line: &[u8]) {
+        let mut line = line;
+        if !self.started {
+            self.started = true;
+            if let Some(rest) = line.strip_prefix(BOM) {
+                self.bom = true;
+                line = rest;
+            }
+        }
+        if let Some(body) = line.strip_suffix(b"\n") {
+            line = match body.strip_suffix(b"\r") {
+                Some(body) => {
+                    self.crlf += 1;
+                    body
+                }
+                None => {
+                    self.lf += 1;
+                    body
+                }
+            };
+        }
+        self.cr += line.iter().filter(|&&b| b == b'\r').count() as u64;
+    }
+
+    /// `LF`, `CRLF` or `CR` if the file uses only that, `mixed` if it uses
+    /// more than one and `none` if it has a single line without an ending.
+    pub fn style(&self) -> &'static str {
+        match (self.lf > 0, self.crlf > 0, self.cr > 0) {
+            (false, false, false) => "none",
+            (true, false, false) => "LF",
+            (false, true, false) => "CRLF",
+            (false, false, true) => "CR",
+            _ => "mixed",
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.style())?;
+        if self.style() == "mixed" {
+            write!(f, " (LF {}, CRLF {}, CR {})", self.lf, self.crlf, self.cr)?;
+        }
+        write!(f, ", {}", if self.bom { "BOM" } else { "no BOM" })
+    }
+}
+
+// -------------------- tests --------------------
+#[cfg(test)]
+mod tests {
+    use super::Report;
+
+    fn report(lines: &[&[u8]]) -> Report {
+        let mut report = Report::default();
+        for line in lines {
+            report.add_line(line);
+        }
+        report
+    }
+
+    #[test]
+    fn test_report() {
+        assert_eq!(report(&[]).to_string(), "none, no BOM");
+        assert_eq!(report(&[b"a\n", b"b"]).to_string(), "LF, no BOM");
+        assert_eq!(report(&[b"\xef\xbb\xbfa\r\n", b"\r\n"]).to_string(), "CRLF, BOM");
+        assert_eq!(report(&[b"a\rb\r"]).to_string(), "CR, no BOM");
+        assert_eq!(report(&[b"a\r\n", b"b\rc\n", b"d\r"]).to_string(), "mixed (LF 1, CRLF 1, CR 2), no BOM");
+        // only a BOM at the very start counts
+        assert_eq!(report(&[b"a\n", b"\xef\xbb\xbfb\n"]).to_string(), "LF, no BOM");
+    }
+}
@@ -0,0 +1,256 @@
+//! `-f`: writes the whole file, then whatever is appended to it.
+//!
+//! The path is watched rather than the open file, the way log rotation
+//! needs it. When the path names a different file than the one being read
+//! (its inode changed, as after `mv app.log app.log.1` and a new
+//! `app.log`), what is left of the old file is written and the new one is
+//! followed from its start. A file that shrinks was truncated and is read
+//! again from its start. Line numbers go on across both.
+//!
+//! On Linux inotify wakes catr up as soon as anything changes; elsewhere,
+//! or if inotify is not available, the file is looked at every second.
+
+use super::{MyResult, Printer};
+use std::fs::{self, File, Metadata};
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+/// Longest wait between two looks at the file.
+const INTERVAL: Duration = Duration::from_secs(1);
+
+/// Follows `path` until catr is killed.
+pub(crate) fn follow<W: Write>(path: &str, printer: &mut Printer<W>) -> MyResult<()> {
+    let file = File::open(path).map_err(|e| format!("Failed to open {path}: {e}"))?;
+    let mut id = identity(&file.metadata()?);
+    let mut reader = BufReader::new(file);
+    let mut pos = 0;
+    // a line whose end has not been written yet
+    let mut line = Vec::new();
+    let mut watcher = Watcher::new(Path::new(path));
+    printer.start_file();
+
+    loop {
+        pos += drain(&mut reader, &mut line, printer)?;
+
+        if let Ok(metadata) = fs::metadata(path)
+            && identity(&metadata) != id
+            && let Ok(file) = File::open(path)
+        {
+            // rotated: finish the old file, which may have got a last write
+            // since it was drained
+            drain(&mut reader, &mut line, printer)?;
+            finish_line(&mut line, printer)?;
+            eprintln!("{path}: file replaced, following the new file");
+            id = identity(&file.metadata()?);
+            reader = BufReader::new(file);
+            pos = 0;
+            watcher.rewatch();
+            continue;
+        }
+        if reader.get_ref().metadata()?.len() < pos {
+            finish_line(&mut line, printer)?;
+            eprintln!("{path}: file truncated");
+            reader.seek(SeekFrom::Start(0))?;
+            pos = 0;
+            continue;
+        }
+
+        watcher.wait()?;
+    }
+}
+
+/// Writes all complete lines `reader` has to offer, keeping a last line
+/// without a newline in `line` until the rest of it arrives. Returns the
+/// number of bytes read.
+fn drain<W: Write>(reader: &mut impl BufRead, line: &mut Vec<u8>, printer: &mut Printer<W>) -> io::Result<u64> {
+    let mut read = 0;
+    loop {
+        let bytes_read = reader.read_until(b'\n', line)?;
+        if bytes_read == 0 {
+            break; // reached EOF, for now
+        }
+        read += bytes_read as u64;
+        if line.last() == Some(&b'\n') {
+            printer.line(line)?;
+            line.clear();
+        }
+    }
+    printer.flush()?;
+    Ok(read)
+}
+
+/// Writes what there is of a line that will not be completed.
+fn finish_line<W: Write>(line: &mut Vec<u8>, printer: &mut Printer<W>) -> io::Result<()> {
+    if !line.is_empty() {
+        printer.line(line)?;
+        line.clear();
+        printer.flush()?;
+    }
+    Ok(())
+}
+
+/// What tells one file from another that took its name.
+#[cfg(unix)]
+fn identity(metadata: &Metadata) -> (u64, u64) {
+    use std::os::unix::fs::MetadataExt;
+    (metadata.dev(), metadata.ino())
+}
+
+#[cfg(not(unix))]
+fn identity(metadata: &Metadata) -> Option<std::time::SystemTime> {
+    metadata.created().ok()
+}
+
+/// Waits for the followed file to change.
+enum Watcher {
+    #[cfg(target_os = "linux")]
+    Inotify(inotify::Inotify),
+    Poll,
+}
+
+impl Watcher {
+    fn new(path: &Path) -> Self {
+        #[cfg(target_os = "linux")]
+        if let Ok(inotify) = inotify::Inotify::new(path) {
+            return Watcher::Inotify(inotify);
+        }
+        let _ = path;
+        Watcher::Poll
+    }
+
+    /// Returns once something may have changed, or after `INTERVAL`.
+    fn wait(&mut self) -> io::Result<()> {
+        match self {
+            #[cfg(target_os = "linux")]
+            Watcher::Inotify(inotify) => inotify.wait(INTERVAL),
+            Watcher::Poll => {
+                thread::sleep(INTERVAL);
+                Ok(())
+            }
+        }
+    }
+
+    /// Watches the file now at the path instead of the one that was.
+    fn rewatch(&mut self) {
+        match self {
+            #[cfg(target_os = "linux")]
+            Watcher::Inotify(inotify) => inotify.rewatch(),
+            Watcher::Poll => {}
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod inotify {
+    use std::ffi::CString;
+    use std::io;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+    use std::time::Duration;
+
+    /// Changes to the file itself.
+    const FILE_EVENTS: u32 = libc::IN_MODIFY | libc::IN_ATTRIB | libc::IN_MOVE_SELF | libc::IN_DELETE_SELF;
+    /// Files appearing in and leaving its directory, for rotation.
+    const DIR_EVENTS: u32 = libc::IN_CREATE | libc::IN_MOVED_TO | libc::IN_MOVED_FROM | libc::IN_DELETE;
+
+    pub(super) struct Inotify {
+        fd: OwnedFd,
+        path: CString,
+        /// the watch on the file, if it was there to be watched
+        file: Option<libc::c_int>,
+    }
+
+    impl Inotify {
+        pub(super) fn new(path: &Path) -> io::Result<Self> {
+            let dir = match path.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => Path::new("."),
+            };
+            let dir = CString::new(dir.as_os_str().as_bytes())?;
+            let path = CString::new(path.as_os_str().as_bytes())?;
+
+            // SAFETY: a new descriptor, owned from here on
+            let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC | libc::IN_NONBLOCK) };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+            // SAFETY: `dir` is a NUL-terminated path
+            if unsafe { libc::inotify_add_watch(fd.as_raw_fd(), dir.as_ptr(), DIR_EVENTS) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let mut inotify = Inotify { fd, path, file: None };
+            inotify.rewatch();
+            Ok(inotify)
+        }
+
+        /// Moves the watch on the file to the file now at the path.
+        pub(super) fn rewatch(&mut self) {
+            let fd = self.fd.as_raw_fd();
+            if let Some(wd) = self.file.take() {
+                // fails harmlessly if the file is gone and took the watch along
+                unsafe { libc::inotify_rm_watch(fd, wd) };
+            }
+            // SAFETY: `path` is a NUL-terminated path
+            let wd = unsafe { libc::inotify_add_watch(fd, self.path.as_ptr(), FILE_EVENTS) };
+            self.file = (wd >= 0).then_some(wd);
+        }
+
+        /// Returns once there were events, which are all read and dropped as
+        /// the caller looks at the file anyway, or after `timeout`.
+        pub(super) fn wait(&mut self, timeout: Duration) -> io::Result<()> {
+            let fd = self.fd.as_raw_fd();
+            let mut pollfd = libc::pollfd { fd, events: libc::POLLIN, revents: 0 };
+            // SAFETY: one valid pollfd
+            if unsafe { libc::poll(&mut pollfd, 1, timeout.as_millis() as libc::c_int) } < 0 {
+                let e = io::Error::last_os_error();
+                return if e.kind() == io::ErrorKind::Interrupted { Ok(()) } else { Err(e) };
+            }
+
+            let mut buf = [0u8; 4096];
+            loop {
+                // SAFETY: reads into `buf` and no further
+                let n = unsafe { libc::read(fd, buf.as_mut_ptr().cast(), buf.len()) };
+                if n <= 0 {
+                    // EAGAIN: no events left
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+// -------------------- tests --------------------
+#[cfg(test)]
+mod tests {
+    use super::{Watcher, INTERVAL};
+    use std::fs;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_watcher() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log");
+        fs::write(&path, "a\n").unwrap();
+        let mut watcher = Watcher::new(&path);
+
+        let writer = {
+            let path = path.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(100));
+                fs::write(path, "b\n").unwrap();
+            })
+        };
+        let start = Instant::now();
+        watcher.wait().unwrap();
+        writer.join().unwrap();
+        #[cfg(target_os = "linux")]
+        assert!(start.elapsed() < INTERVAL, "woke up after {:?}", start.elapsed());
+        #[cfg(not(target_os = "linux"))]
+        assert!(start.elapsed() >= INTERVAL);
+    }
+}
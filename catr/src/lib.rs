@@ -1,7 +1,20 @@
 use std::error::Error;
-use clap::{Arg, Command, ArgAction};
+use clap::{value_parser, Arg, ArgAction, ArgGroup, Command};
+use regex::bytes::Regex;
 use std::fs::File;
-use std::io::{self, BufRead, BufReader};
+use std::io::{self, BufRead, BufReader, BufWriter, Cursor, Read, Write};
+
+mod copy;
+mod decompress;
+pub mod encoding;
+pub mod endings;
+mod follow;
+pub mod numbering;
+
+use decompress::Decoded;
+use encoding::{OnError, Transcode};
+use endings::{Bom, Newline, Report};
+use numbering::{LineNumber, Numbering, Style};
 
 
 #[derive(Debug, Clone, PartialEq)]
@@ -9,62 +22,315 @@ use std::io::{self, BufRead, BufReader};
 #[allow(dead_code)]
 pub struct Config {
     files: Vec<String>,
-    number_lines: bool,
-    number_nonblank_lines: bool,
+    number: Option<Numbering>,
+    squeeze_blank: bool,
+    show_ends: bool,
+    show_tabs: bool,
+    show_nonprinting: bool,
+    decompress: bool,
+    follow: bool,
+    newline: Option<Newline>,
+    bom: Option<Bom>,
+    detect: bool,
+    encoding: Option<Transcode>,
 }
 
 
 type MyResult<T> = Result<T, Box<dyn Error>>;
 
-fn open(filename: &str) -> MyResult<Box<dyn BufRead>> {
-    match filename {
-        "-" => Ok(Box::new(BufReader::new(io::stdin()))),
-        _ => Ok(Box::new(BufReader::new(File::open(filename)?))),
+fn open(filename: &str, config: &Config) -> MyResult<BufReader<Box<dyn Read>>> {
+    let mut input: Box<dyn Read> = match filename {
+        "-" => Box::new(io::stdin()),
+        _ => Box::new(File::open(filename)?),
+    };
+    if config.decompress {
+        let (header, codec) = decompress::sniff(&mut input)?;
+        input = match codec {
+            Some(codec) => match codec.decode(header, input)? {
+                Decoded::Text(text) => text,
+                Decoded::Raw(raw, input) => Box::new(Cursor::new(raw).chain(input)),
+            },
+            None => Box::new(Cursor::new(header).chain(input)),
+        };
+    }
+    if let Some(transcode) = &config.encoding {
+        input = Box::new(encoding::Decode::new(filename, input, transcode)?);
+    }
+    Ok(BufReader::new(input))
+}
+
+/// Where the lines go: stdout, in the --to-encoding.
+fn stdout(config: &Config) -> Box<dyn Write> {
+    let out = BufWriter::new(io::stdout().lock());
+    match &config.encoding {
+        Some(transcode) => Box::new(encoding::Encode::new(out, transcode)),
+        None => Box::new(out),
+    }
+}
+
+impl Config {
+    /// Whether the files are written out as they are.
+    fn is_plain(&self) -> bool {
+        !(self.number.is_some()
+            || self.squeeze_blank
+            || self.show_ends
+            || self.show_tabs
+            || self.show_nonprinting
+            || self.newline.is_some()
+            || self.bom.is_some()
+            || self.encoding.is_some())
     }
 }
 
 pub fn run(config: Config) -> MyResult<()> {
-    let mut line_num = 1;
+    if config.follow {
+        let mut printer = Printer::new(&config, stdout(&config));
+        return follow::follow(&config.files[0], &mut printer);
+    }
+    if config.detect {
+        return run_detect(&config);
+    }
+    if config.is_plain() {
+        return run_plain(&config);
+    }
+
+    let mut printer = Printer::new(&config, stdout(&config));
+
     for filename in &config.files {
-        match open(filename) {
+        match open(filename, &config) {
             Err(e) => eprintln!("Failed to open {filename}: {e}"),
             Ok(mut reader) => {
-                let mut line = String::new();
+                printer.start_file();
+                let mut line = Vec::new();
                 
                 loop {
-                    let bytes_read = reader.read_line(&mut line)?;
+                    let bytes_read = reader.read_until(b'\n', &mut line)?;
                     if bytes_read == 0 {
                         break; // reached EOF
                     }
-                    
-                    // -n option: number all lines
-                    if config.number_lines {
-                        print!("{:>6}\t{}", line_num, line);
-                        line_num += 1;
-                    }
-                    // -b option: number nonblank lines
-                    else if config.number_nonblank_lines {
-                        if line.trim().is_empty() {
-                            print!("{}", line); // blank line has no line number
-                        } else {
-                            print!("{:>6}\t{}", line_num, line);
-                            line_num += 1;
-                        }
-                    }
-                    // no option: display lines without line numbers
-                    else {
-                        print!("{}", line);
-                    }
-                    
+                    printer.line(&line)?;
                     line.clear(); // clear for next line
+                    // nothing more to hand: let the lines so far out before
+                    // waiting for the input, a pipe may take its time
+                    if reader.buffer().is_empty() {
+                        printer.flush()?;
+                    }
                 }
             }
         }
     }
+    printer.flush()?;
     // dbg!(config);
     Ok(())
 }
 
+/// Writes lines the way the options of a `Config` say, keeping the state
+/// that carries over from one line, and file, to the next.
+pub(crate) struct Printer<'a, W> {
+    config: &'a Config,
+    out: W,
+    line_number: Option<LineNumber<'a>>,
+    prev_blank: bool,
+    /// whether the next line is the first of a file
+    file_start: bool,
+    /// whether anything was written yet
+    started: bool,
+}
+
+impl<'a, W: Write> Printer<'a, W> {
+    fn new(config: &'a Config, out: W) -> Self {
+        let line_number = config.number.as_ref().map(LineNumber::new);
+        Printer { config, out, line_number, prev_blank: false, file_start: true, started: false }
+    }
+
+    /// Called before the first line of every file.
+    fn start_file(&mut self) {
+        self.file_start = true;
+        if let Some(line_number) = &mut self.line_number {
+            line_number.start_file();
+        }
+    }
+
+    /// Writes `line`, which ends in a newline unless it is the last of a file.
+    pub(crate) fn line(&mut self, line: &[u8]) -> io::Result<()> {
+        let mut line = line;
+        // --strip-bom and --add-bom options
+        if std::mem::take(&mut self.file_start) && self.config.bom.is_some() {
+            line = line.strip_prefix(endings::BOM).unwrap_or(line);
+            if line.is_empty() {
+                return Ok(());
+            }
+        }
+        if !std::mem::replace(&mut self.started, true) && self.config.bom == Some(Bom::Add) {
+            self.out.write_all(endings::BOM)?;
+        }
+        // --crlf-to-lf and --lf-to-crlf options: every line ends in LF
+        // from here on, render() adds the CR back for CRLF
+        let lf;
+        if self.config.newline.is_some()
+            && let Some(body) = line.strip_suffix(b"\r\n")
+        {
+            lf = [body, b"\n"].concat();
+            line = &lf;
+        }
+
+        // -s option: keep only the first of consecutive empty lines,
+        // also across files
+        let blank = line == b"\n";
+        if self.config.squeeze_blank && blank && self.prev_blank {
+            return Ok(());
+        }
+        self.prev_blank = blank;
+
+        // -n, -b and --number-only-matching options
+        if let Some(line_number) = &mut self.line_number {
+            line_number.write(line, &mut self.out)?;
+        }
+        render(line, self.config, &mut self.out)
+    }
+
+    pub(crate) fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// Writes the line endings and BOM of every file instead of the files.
+fn run_detect(config: &Config) -> MyResult<()> {
+    let mut out = BufWriter::new(io::stdout().lock());
+    for filename in &config.files {
+        match open(filename, config) {
+            Err(e) => eprintln!("Failed to open {filename}: {e}"),
+            Ok(mut reader) => {
+                let mut report = Report::default();
+                let mut line = Vec::new();
+                while reader.read_until(b'\n', &mut line)? > 0 {
+                    report.add_line(&line);
+                    line.clear();
+                }
+                writeln!(out, "{filename}: {report}")?;
+            }
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Concatenates the files without looking at their lines.
+fn run_plain(config: &Config) -> MyResult<()> {
+    let mut out = io::stdout().lock();
+    for filename in &config.files {
+        match filename.as_str() {
+            "-" => copy_plain(&mut stdin_unbuffered()?, &mut out, config.decompress)?,
+            _ => match File::open(filename) {
+                Err(e) => {
+                    eprintln!("Failed to open {filename}: {e}");
+                    continue;
+                }
+                Ok(mut file) => copy_plain(&mut file, &mut out, config.decompress)?,
+            },
+        };
+    }
+    Ok(())
+}
+
+/// Stdin without the buffer of `io::Stdin`, so that what is read by hand
+/// and what the kernel copies from the descriptor line up.
+#[cfg(unix)]
+fn stdin_unbuffered() -> io::Result<File> {
+    use std::os::fd::AsFd;
+    Ok(File::from(io::stdin().as_fd().try_clone_to_owned()?))
+}
+
+#[cfg(not(unix))]
+fn stdin_unbuffered() -> io::Result<io::StdinLock<'static>> {
+    Ok(io::stdin().lock())
+}
+
+/// Copies `input` to `out`, decompressed if it is compressed and
+/// `decompress` is set.
+fn copy_plain<R, W>(input: &mut R, out: &mut W, decompress: bool) -> io::Result<()>
+where
+    R: Read + copy::Fd,
+    W: Write + copy::Fd,
+{
+    if decompress {
+        let (header, codec) = decompress::sniff(input)?;
+        let raw = match codec {
+            Some(codec) => match codec.decode(header, &mut *input)? {
+                Decoded::Text(mut text) => {
+                    copy::buffered(&mut text, out)?;
+                    return Ok(());
+                }
+                Decoded::Raw(raw, _) => raw,
+            },
+            None => header,
+        };
+        out.write_all(&raw)?;
+    }
+    copy::copy(input, out)?;
+    Ok(())
+}
+
+/// Writes `line`, which may end in a newline, with the -v, -E and -T
+/// notations applied and, for --lf-to-crlf, a CR before the newline.
+fn render(line: &[u8], config: &Config, out: &mut impl Write) -> io::Result<()> {
+    let crlf = config.newline == Some(Newline::Crlf);
+    if !(config.show_nonprinting || config.show_ends || config.show_tabs || crlf) {
+        return out.write_all(line);
+    }
+
+    let (body, newline) = match line.strip_suffix(b"\n") {
+        Some(body) => (body, true),
+        None => (line, false),
+    };
+    // -E option: like GNU cat, a CR ending the line shows as ^M
+    let (body, cr) = match body.strip_suffix(b"\r") {
+        Some(body) if newline && config.show_ends => (body, true),
+        _ => (body, false),
+    };
+    let mut rendered = Vec::with_capacity(line.len() + 3);
+    for &b in body {
+        match b {
+            // -T option: tabs as ^I
+            b'\t' if config.show_tabs => rendered.extend_from_slice(b"^I"),
+            b'\t' => rendered.push(b),
+            // -v option: ^ and M- notation for everything else unprintable
+            _ if config.show_nonprinting => push_visible(&mut rendered, b),
+            _ => rendered.push(b),
+        }
+    }
+    if cr {
+        rendered.extend_from_slice(b"^M");
+    }
+    if newline {
+        // -E option: $ at the end of each line
+        if config.show_ends {
+            rendered.push(b'$');
+        }
+        if crlf {
+            rendered.push(b'\r');
+        }
+        rendered.push(b'\n');
+    }
+    out.write_all(&rendered)
+}
+
+/// Pushes `b` the way `cat -v` shows it: bytes from 128 up as `M-` and the
+/// byte 128 lower, then control characters as `^@` to `^_` and DEL as `^?`.
+fn push_visible(rendered: &mut Vec<u8>, b: u8) {
+    let b = if b >= 128 {
+        rendered.extend_from_slice(b"M-");
+        b - 128
+    } else {
+        b
+    };
+    match b {
+        0..=31 => rendered.extend_from_slice(&[b'^', b + 64]),
+        127 => rendered.extend_from_slice(b"^?"),
+        _ => rendered.push(b),
+    }
+}
+
 pub fn get_args() -> MyResult<Config> {
     let matches = Command::new("catr")
         .version("0.1.0")
@@ -80,25 +346,278 @@ pub fn get_args() -> MyResult<Config> {
         .arg(
             Arg::new("number_lines")
             .short('n')
+            .long("number")
             .help("Number lines")
             .action(ArgAction::SetTrue),
         )
         .arg(
             Arg::new("number_nonblank_lines")
             .short('b')
+            .long("number-nonblank")
             .help("Number nonblank lines")
             .action(ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("number_only_matching")
+            .long("number-only-matching")
+            .value_name("PATTERN")
+            .help("Number only lines matching PATTERN")
+            .conflicts_with_all(["number_lines", "number_nonblank_lines"]),
+        )
+        .group(
+            ArgGroup::new("numbering")
+            .args(["number_lines", "number_nonblank_lines", "number_only_matching"])
+            .multiple(true),
+        )
+        .arg(
+            Arg::new("number_width")
+            .long("number-width")
+            .value_name("WIDTH")
+            .help("Use WIDTH columns for line numbers")
+            .value_parser(value_parser!(u64).range(1..))
+            .default_value("6")
+            .requires("numbering"),
+        )
+        .arg(
+            Arg::new("number_separator")
+            .long("number-separator")
+            .value_name("STRING")
+            .help("Add STRING after line numbers")
+            .default_value("\t")
+            .hide_default_value(true)
+            .requires("numbering"),
+        )
+        .arg(
+            Arg::new("starting_line_number")
+            .long("starting-line-number")
+            .value_name("NUMBER")
+            .help("First line number")
+            .value_parser(value_parser!(i64))
+            .allow_negative_numbers(true)
+            .default_value("1")
+            .requires("numbering"),
+        )
+        .arg(
+            Arg::new("line_increment")
+            .long("line-increment")
+            .value_name("NUMBER")
+            .help("Line number increment")
+            .value_parser(value_parser!(i64))
+            .allow_negative_numbers(true)
+            .default_value("1")
+            .requires("numbering"),
+        )
+        .arg(
+            Arg::new("number_format")
+            .long("number-format")
+            .value_name("FORMAT")
+            .help("Line numbers left justified (ln), right justified (rn) or with leading zeros (rz)")
+            .value_parser(["ln", "rn", "rz"])
+            .default_value("rn")
+            .requires("numbering"),
+        )
+        .arg(
+            Arg::new("reset_per_file")
+            .long("reset-per-file")
+            .help("Start numbering again at every file")
+            .action(ArgAction::SetTrue)
+            .requires("numbering"),
+        )
+        .arg(
+            Arg::new("squeeze_blank")
+            .short('s')
+            .long("squeeze-blank")
+            .help("Suppress repeated empty lines")
+            .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("show_all")
+            .short('A')
+            .long("show-all")
+            .help("Equivalent to -vET")
+            .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("e")
+            .short('e')
+            .help("Equivalent to -vE")
+            .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("show_ends")
+            .short('E')
+            .long("show-ends")
+            .help("Display $ at end of each line")
+            .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("t")
+            .short('t')
+            .help("Equivalent to -vT")
+            .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("show_tabs")
+            .short('T')
+            .long("show-tabs")
+            .help("Display TAB characters as ^I")
+            .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("show_nonprinting")
+            .short('v')
+            .long("show-nonprinting")
+            .help("Use ^ and M- notation, except for LFD and TAB")
+            .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("crlf_to_lf")
+            .long("crlf-to-lf")
+            .help("End lines with LF instead of CRLF")
+            .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("lf_to_crlf")
+            .long("lf-to-crlf")
+            .help("End lines with CRLF instead of LF")
+            .action(ArgAction::SetTrue)
+            .conflicts_with("crlf_to_lf"),
+        )
+        .arg(
+            Arg::new("strip_bom")
+            .long("strip-bom")
+            .help("Remove the UTF-8 byte order mark at the start of each file")
+            .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("add_bom")
+            .long("add-bom")
+            .help("Start the output with a UTF-8 byte order mark, and only there")
+            .action(ArgAction::SetTrue)
+            .conflicts_with("strip_bom"),
+        )
+        .arg(
+            Arg::new("detect")
+            .long("detect")
+            .help("Report the line endings and byte order mark of each file instead")
+            .action(ArgAction::SetTrue)
+            .conflicts_with_all(["from_encoding", "to_encoding"]),
+        )
+        .arg(
+            Arg::new("from_encoding")
+            .long("from-encoding")
+            .value_name("ENCODING")
+            .help("Read input in ENCODING, or tell it from a BOM or the text with auto [default: auto]"),
+        )
+        .arg(
+            Arg::new("to_encoding")
+            .long("to-encoding")
+            .value_name("ENCODING")
+            .help("Write output in ENCODING [default: utf-8]"),
+        )
+        .group(
+            ArgGroup::new("encoding")
+            .args(["from_encoding", "to_encoding"])
+            .multiple(true),
+        )
+        .arg(
+            Arg::new("on_error")
+            .long("on-error")
+            .value_name("POLICY")
+            .help("What to do with invalid input and characters the output encoding lacks")
+            .value_parser(["replace", "fail", "skip"])
+            .default_value("replace")
+            .requires("encoding"),
+        )
+        .arg(
+            Arg::new("follow")
+            .short('f')
+            .long("follow")
+            .help("Keep writing what is appended to FILE, also after it is rotated")
+            .action(ArgAction::SetTrue)
+            .conflicts_with("from_encoding"),
+        )
+        .arg(
+            Arg::new("no_decompress")
+            .long("no-decompress")
+            .help("Write compressed input as it is")
+            .action(ArgAction::SetTrue),
+        )
         .get_matches();
 
+    let style = if matches.get_flag("number_lines") {
+        Some(Style::All)
+    } else if matches.get_flag("number_nonblank_lines") {
+        Some(Style::NonBlank)
+    } else if let Some(pattern) = matches.get_one::<String>("number_only_matching") {
+        Some(Style::Matching(Regex::new(pattern).map_err(|_| format!("invalid number regex -- {}", pattern))?))
+    } else {
+        None
+    };
+    let number = style.map(|style| Numbering {
+        style,
+        format: numbering::Format::parse(matches.get_one::<String>("number_format").unwrap()),
+        width: *matches.get_one::<u64>("number_width").unwrap() as usize,
+        separator: matches.get_one::<String>("number_separator").unwrap().to_string(),
+        start: *matches.get_one::<i64>("starting_line_number").unwrap(),
+        increment: *matches.get_one::<i64>("line_increment").unwrap(),
+        reset_per_file: matches.get_flag("reset_per_file"),
+    });
+
+    let files: Vec<String> = matches
+        .get_many::<String>("files")
+        .unwrap_or_default()
+        .map(|s| s.to_string())
+        .collect();
+    let follow = matches.get_flag("follow");
+    if follow && (files.len() != 1 || files[0] == "-") {
+        return Err("--follow requires a single FILE other than -".into());
+    }
+
+    let encoding = if matches.contains_id("encoding") {
+        let from = match matches.get_one::<String>("from_encoding").map(String::as_str) {
+            None | Some("auto") => None,
+            Some(label) => Some(encoding::parse_encoding(label)?),
+        };
+        let to = matches.get_one::<String>("to_encoding").map_or("utf-8", String::as_str);
+        Some(Transcode {
+            from,
+            to: encoding::parse_encoding(to)?,
+            on_error: OnError::parse(matches.get_one::<String>("on_error").unwrap()),
+        })
+    } else {
+        None
+    };
+
+    let show_all = matches.get_flag("show_all");
     Ok(Config {
-        files: matches
-            .get_many::<String>("files")
-            .unwrap_or_default()
-            .map(|s| s.to_string())
-            .collect(),
-        number_lines: matches.get_flag("number_lines"),
-        number_nonblank_lines: matches.get_flag("number_nonblank_lines"),
+        files,
+        number,
+        squeeze_blank: matches.get_flag("squeeze_blank"),
+        show_ends: show_all || matches.get_flag("e") || matches.get_flag("show_ends"),
+        show_tabs: show_all || matches.get_flag("t") || matches.get_flag("show_tabs"),
+        show_nonprinting: show_all
+            || matches.get_flag("e")
+            || matches.get_flag("t")
+            || matches.get_flag("show_nonprinting"),
+        decompress: !matches.get_flag("no_decompress"),
+        follow,
+        newline: if matches.get_flag("crlf_to_lf") {
+            Some(Newline::Lf)
+        } else if matches.get_flag("lf_to_crlf") {
+            Some(Newline::Crlf)
+        } else {
+            None
+        },
+        bom: if matches.get_flag("strip_bom") {
+            Some(Bom::Strip)
+        } else if matches.get_flag("add_bom") {
+            Some(Bom::Add)
+        } else {
+            None
+        },
+        detect: matches.get_flag("detect"),
+        encoding,
     })
 
-}
\ No newline at end of file
+}
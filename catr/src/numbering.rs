@@ -0,0 +1,180 @@
+//! Line numbering for `-n`, `-b` and `--number-only-matching`, laid out the
+//! way `nl` does it with `--number-width`, `--number-separator`,
+//! `--number-format`, `--starting-line-number` and `--line-increment`.
+
+use regex::bytes::Regex;
+use std::io::{self, Write};
+
+/// Which lines get a number and how it looks.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Numbering {
+    pub style: Style,
+    pub format: Format,
+    pub width: usize,
+    pub separator: String,
+    pub start: i64,
+    pub increment: i64,
+    /// start again from `start` at every file instead of counting on
+    pub reset_per_file: bool,
+}
+
+/// Which lines are numbered.
+#[derive(Debug, Clone)]
+pub enum Style {
+    /// every line (`-n`)
+    All,
+    /// lines with something besides whitespace (`-b`)
+    NonBlank,
+    /// lines matching a pattern (`--number-only-matching`)
+    Matching(Regex),
+}
+
+impl PartialEq for Style {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Style::All, Style::All) | (Style::NonBlank, Style::NonBlank) => true,
+            (Style::Matching(a), Style::Matching(b)) => a.as_str() == b.as_str(),
+            _ => false,
+        }
+    }
+}
+
+/// How a number is placed in its `width`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// left justified (`ln`)
+    Left,
+    /// right justified (`rn`, the default)
+    Right,
+    /// right justified with leading zeros (`rz`)
+    Zeros,
+}
+
+impl Format {
+    pub fn parse(format: &str) -> Self {
+        match format {
+            "ln" => Format::Left,
+            "rz" => Format::Zeros,
+            _ => Format::Right,
+        }
+    }
+}
+
+impl Default for Numbering {
+    fn default() -> Self {
+        Numbering {
+            style: Style::All,
+            format: Format::Right,
+            width: 6,
+            separator: "\t".to_string(),
+            start: 1,
+            increment: 1,
+            reset_per_file: false,
+        }
+    }
+}
+
+/// The running line number of a `Numbering`.
+pub(crate) struct LineNumber<'a> {
+    numbering: &'a Numbering,
+    /// `None` once the numbers ran past `i64`
+    next: Option<i64>,
+}
+
+impl<'a> LineNumber<'a> {
+    pub(crate) fn new(numbering: &'a Numbering) -> Self {
+        LineNumber { numbering, next: Some(numbering.start) }
+    }
+
+    /// Called before the first line of every file.
+    pub(crate) fn start_file(&mut self) {
+        if self.numbering.reset_per_file {
+            self.next = Some(self.numbering.start);
+        }
+    }
+
+    /// Writes the number for `line`, which may end in a newline, if it gets
+    /// one.
+    pub(crate) fn write(&mut self, line: &[u8], out: &mut impl Write) -> io::Result<()> {
+        let numbering = self.numbering;
+        let numbered = match &numbering.style {
+            Style::All => true,
+            Style::NonBlank => !line.trim_ascii().is_empty(),
+            Style::Matching(re) => re.is_match(line.strip_suffix(b"\n").unwrap_or(line)),
+        };
+        if !numbered {
+            return Ok(());
+        }
+
+        let n = self.next.ok_or_else(|| io::Error::other("line number overflow"))?;
+        let (width, sep) = (numbering.width, &numbering.separator);
+        match numbering.format {
+            Format::Left => write!(out, "{n:<width$}{sep}")?,
+            Format::Right => write!(out, "{n:>width$}{sep}")?,
+            Format::Zeros => write!(out, "{n:0width$}{sep}")?,
+        }
+        self.next = n.checked_add(numbering.increment);
+        Ok(())
+    }
+}
+
+// -------------------- tests --------------------
+#[cfg(test)]
+mod tests {
+    use super::{Format, LineNumber, Numbering, Style};
+    use regex::bytes::Regex;
+
+    fn numbers(numbering: &Numbering, files: &[&[&str]]) -> String {
+        let mut line_number = LineNumber::new(numbering);
+        let mut out = Vec::new();
+        for lines in files {
+            line_number.start_file();
+            for line in *lines {
+                line_number.write(line.as_bytes(), &mut out).unwrap();
+                out.extend(line.as_bytes());
+            }
+        }
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_format() {
+        let numbering = Numbering { width: 3, separator: ": ".to_string(), ..Numbering::default() };
+        assert_eq!(numbers(&numbering, &[&["a\n", "b\n"]]), "  1: a\n  2: b\n");
+        let numbering = Numbering { format: Format::Left, width: 3, ..numbering };
+        assert_eq!(numbers(&numbering, &[&["a\n"]]), "1  : a\n");
+        let numbering = Numbering { format: Format::Zeros, start: -2, ..numbering };
+        assert_eq!(numbers(&numbering, &[&["a\n", "b\n", "c\n"]]), "-02: a\n-01: b\n000: c\n");
+        // numbers wider than `width` are not cut
+        let numbering = Numbering { format: Format::Right, width: 1, start: 10, ..numbering };
+        assert_eq!(numbers(&numbering, &[&["a\n"]]), "10: a\n");
+    }
+
+    #[test]
+    fn test_style() {
+        let files: &[&[&str]] = &[&["a\n", " \n", "b"], &["\n", "c\n"]];
+        let numbering = Numbering { width: 1, separator: " ".to_string(), ..Numbering::default() };
+        assert_eq!(numbers(&numbering, files), "1 a\n2  \n3 b4 \n5 c\n");
+
+        let numbering = Numbering { style: Style::NonBlank, increment: 5, ..numbering };
+        assert_eq!(numbers(&numbering, files), "1 a\n \n6 b\n11 c\n");
+
+        let numbering = Numbering { reset_per_file: true, ..numbering };
+        assert_eq!(numbers(&numbering, files), "1 a\n \n6 b\n1 c\n");
+
+        // the pattern does not see the newline
+        let numbering = Numbering { style: Style::Matching(Regex::new("^[ab]$").unwrap()), ..numbering };
+        assert_eq!(numbers(&numbering, files), "1 a\n \n6 b\nc\n");
+    }
+
+    #[test]
+    fn test_overflow() {
+        let numbering = Numbering { start: i64::MAX, ..Numbering::default() };
+        let mut line_number = LineNumber::new(&numbering);
+        let mut out = Vec::new();
+        line_number.write(b"a\n", &mut out).unwrap();
+        assert_eq!(out, format!("{}\t", i64::MAX).as_bytes());
+        let err = line_number.write(b"a\n", &mut out).unwrap_err();
+        assert_eq!(err.to_string(), "line number overflow");
+    }
+}
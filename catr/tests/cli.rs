@@ -1,3 +1,6 @@
+// the older tests pass their arguments as `&[..]`
+#![allow(clippy::needless_borrows_for_generic_args)]
+
 use assert_cmd::Command;
 use predicates::prelude::*;
 use std::fs;
@@ -164,4 +167,537 @@ fn run_with_one_file_of_multiple_lines_with_number_lines_case2() -> TestResult {
     .success()
     .stdout(expected);
     Ok(())
-}
\ No newline at end of file
+}
+#[test]
+fn run_with_visual_options() -> TestResult {
+    for opt in ["s", "E", "T", "v", "A", "e", "t", "sn"] {
+        let expected = fs::read(format!("tests/expected/visual.{opt}.txt"))?;
+        let mut cmd = Command::cargo_bin("catr")?;
+        cmd.args([format!("-{opt}").as_str(), "tests/inputs/visual.txt"])
+        .assert()
+        .success()
+        .stdout(expected);
+    }
+    Ok(())
+}
+
+#[test]
+fn run_with_long_visual_options() -> TestResult {
+    let expected = fs::read("tests/expected/visual.A.txt")?;
+    let mut cmd = Command::cargo_bin("catr")?;
+    cmd.args(["--show-nonprinting", "--show-ends", "--show-tabs", "tests/inputs/visual.txt"])
+    .assert()
+    .success()
+    .stdout(expected);
+    Ok(())
+}
+
+#[test]
+fn run_with_squeeze_blank_across_files() -> TestResult {
+    let mut cmd = Command::cargo_bin("catr")?;
+    cmd.args(["-s", "-", "tests/inputs/blank-lines.txt"])
+    .write_stdin("first\n\n")
+    .assert()
+    .success()
+    .stdout("first\n\nlast\n");
+    Ok(())
+}
+
+#[test]
+fn run_with_non_utf8_file() -> TestResult {
+    let expected = fs::read("tests/inputs/latin1.txt")?;
+    let mut cmd = Command::cargo_bin("catr")?;
+    cmd.arg("tests/inputs/latin1.txt")
+    .assert()
+    .success()
+    .stdout(expected);
+    Ok(())
+}
+
+#[test]
+fn run_with_non_utf8_files_concatenated() -> TestResult {
+    let mut expected = fs::read("tests/inputs/latin1.txt")?;
+    expected.extend(fs::read("tests/inputs/fox.txt")?);
+    let mut cmd = Command::cargo_bin("catr")?;
+    cmd.args(["tests/inputs/latin1.txt", "tests/inputs/fox.txt"])
+    .assert()
+    .success()
+    .stdout(expected);
+    Ok(())
+}
+
+#[test]
+fn run_with_non_utf8_file_with_number_lines() -> TestResult {
+    let expected = fs::read("tests/expected/latin1.n.txt")?;
+    let mut cmd = Command::cargo_bin("catr")?;
+    cmd.args(["-n", "tests/inputs/latin1.txt"])
+    .assert()
+    .success()
+    .stdout(expected);
+    Ok(())
+}
+
+#[test]
+fn run_with_large_binary_stdin() -> TestResult {
+    let mut rng = rand::rng();
+    let mut input: Vec<u8> = (0..3 << 20).map(|_| rng.random()).collect();
+    input[0] = b'x'; // not the magic number of a compressed stream
+    let mut cmd = Command::cargo_bin("catr")?;
+    cmd.arg("-")
+    .write_stdin(input.clone())
+    .assert()
+    .success()
+    .stdout(input);
+    Ok(())
+}
+
+#[test]
+fn run_with_files_and_stdin_around_a_bad_file() -> TestResult {
+    let bad = gen_bad_file();
+    let mut expected = fs::read("tests/inputs/latin1.txt")?;
+    expected.extend(b"from stdin\n");
+    expected.extend(fs::read("tests/inputs/fox.txt")?);
+    let mut cmd = Command::cargo_bin("catr")?;
+    cmd.args(["tests/inputs/latin1.txt", &bad, "-", "tests/inputs/fox.txt"])
+    .write_stdin("from stdin\n")
+    .assert()
+    .success()
+    .stdout(expected)
+    .stderr(predicate::str::contains(bad));
+    Ok(())
+}
+
+#[test]
+fn run_with_number_format_options() -> TestResult {
+    let expected = fs::read_to_string("tests/expected/the-bustle.n.rz.txt")?;
+    let mut cmd = Command::cargo_bin("catr")?;
+    cmd.args([
+        "-n",
+        "--number-width", "3",
+        "--number-separator", ": ",
+        "--number-format", "rz",
+        "--starting-line-number", "-2",
+        "--line-increment", "3",
+        "tests/inputs/the-bustle.txt",
+    ])
+    .assert()
+    .success()
+    .stdout(expected);
+    Ok(())
+}
+
+#[test]
+fn run_with_number_format_left() -> TestResult {
+    let expected = fs::read_to_string("tests/expected/the-bustle.n.ln.txt")?;
+    let mut cmd = Command::cargo_bin("catr")?;
+    cmd.args(["-n", "--number-format=ln", "tests/inputs/the-bustle.txt"])
+    .assert()
+    .success()
+    .stdout(expected);
+    Ok(())
+}
+
+#[test]
+fn run_with_reset_per_file() -> TestResult {
+    let mut cmd = Command::cargo_bin("catr")?;
+    cmd.args(["-b", "--reset-per-file", "--number-width=1", "-", "tests/inputs/spiders.txt"])
+    .write_stdin("a\n\nb\n")
+    .assert()
+    .success()
+    .stdout("1\ta\n\n2\tb\n1\tDon't worry, spiders,\n2\tI keep house\n3\tcasually.");
+    Ok(())
+}
+
+#[test]
+fn run_with_number_only_matching() -> TestResult {
+    let mut cmd = Command::cargo_bin("catr")?;
+    cmd.args(["--number-only-matching", "^(The|And) ", "--number-width=2", "tests/inputs/the-bustle.txt"])
+    .assert()
+    .success()
+    .stdout(predicate::str::starts_with(" 1\tThe bustle in a house\n 2\tThe morning after death\nIs solemnest"))
+    .stdout(predicate::str::contains("\n 3\tThe sweeping up the heart,\n 4\tAnd putting love away\nWe shall"));
+    Ok(())
+}
+
+#[test]
+fn number_options_need_numbering() -> TestResult {
+    let mut cmd = Command::cargo_bin("catr")?;
+    cmd.args(["--number-width", "3", "tests/inputs/fox.txt"])
+    .assert()
+    .failure()
+    .stderr(predicate::str::contains("required arguments were not provided"));
+    Ok(())
+}
+
+#[test]
+fn dies_bad_number_only_matching() -> TestResult {
+    let mut cmd = Command::cargo_bin("catr")?;
+    cmd.args(["--number-only-matching", "(", "tests/inputs/fox.txt"])
+    .assert()
+    .failure()
+    .stderr("invalid number regex -- (\n");
+    Ok(())
+}
+
+#[cfg(all(feature = "gzip", feature = "bzip2", feature = "xz", feature = "zstd"))]
+#[test]
+fn run_with_compressed_files() -> TestResult {
+    let expected = fs::read_to_string("tests/inputs/fox.txt")?;
+    for ext in ["gz", "bz2", "xz", "zst"] {
+        let mut cmd = Command::cargo_bin("catr")?;
+        cmd.arg(format!("tests/inputs/fox.txt.{ext}"))
+        .assert()
+        .success()
+        .stdout(expected.clone());
+    }
+    Ok(())
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn run_with_concatenated_gzip_members_and_number_lines() -> TestResult {
+    let mut cmd = Command::cargo_bin("catr")?;
+    cmd.args(["-n", "tests/inputs/fox-spiders.txt.gz"])
+    .assert()
+    .success()
+    .stdout("     1\tThe quick brown fox jumps over the lazy dog.Don't worry, spiders,\n     2\tI keep house\n     3\tcasually.");
+    Ok(())
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn run_with_compressed_stdin() -> TestResult {
+    let mut expected = fs::read("tests/inputs/fox.txt")?;
+    expected.extend(fs::read("tests/inputs/spiders.txt")?);
+    let mut cmd = Command::cargo_bin("catr")?;
+    cmd.args(["-", "tests/inputs/spiders.txt"])
+    .write_stdin(fs::read("tests/inputs/fox.txt.zst")?)
+    .assert()
+    .success()
+    .stdout(expected);
+    Ok(())
+}
+
+#[test]
+fn run_with_text_that_looks_compressed() -> TestResult {
+    let mut expected = Vec::new();
+    for file in ["not-bzip2.txt", "looks-bzip2.txt", "fox.txt"] {
+        expected.extend(fs::read(format!("tests/inputs/{file}"))?);
+    }
+    // as they are, also when the decoder gave up on them
+    let mut cmd = Command::cargo_bin("catr")?;
+    cmd.args(["tests/inputs/not-bzip2.txt", "tests/inputs/looks-bzip2.txt", "tests/inputs/fox.txt"])
+    .assert()
+    .success()
+    .stdout(expected);
+
+    let mut cmd = Command::cargo_bin("catr")?;
+    cmd.args(["-n", "tests/inputs/not-bzip2.txt", "tests/inputs/looks-bzip2.txt", "-"])
+    .write_stdin("BZh91AY&SY\n")
+    .assert()
+    .success()
+    .stdout("     1\tBZh is a nice word\n     2\tBZh91AY&SY is how bzip2 starts\n     3\tBZh91AY&SY\n");
+    Ok(())
+}
+
+#[test]
+fn run_with_no_decompress() -> TestResult {
+    let expected = fs::read("tests/inputs/fox.txt.gz")?;
+    let mut cmd = Command::cargo_bin("catr")?;
+    cmd.args(["--no-decompress", "tests/inputs/fox.txt.gz"])
+    .assert()
+    .success()
+    .stdout(expected.clone());
+    let mut cmd = Command::cargo_bin("catr")?;
+    cmd.args(["--no-decompress", "-"])
+    .write_stdin(expected.clone())
+    .assert()
+    .success()
+    .stdout(expected);
+    Ok(())
+}
+
+/// Reads what a followed file brings until `expected` shows up.
+fn wait_for(output: &std::sync::mpsc::Receiver<Vec<u8>>, seen: &mut Vec<u8>, expected: &str) {
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+    while !String::from_utf8_lossy(seen).contains(expected) {
+        let left = deadline.saturating_duration_since(std::time::Instant::now());
+        match output.recv_timeout(left) {
+            Ok(chunk) => seen.extend(chunk),
+            Err(_) => panic!("no {expected:?} in {:?}", String::from_utf8_lossy(seen)),
+        }
+    }
+}
+
+#[test]
+fn follow_appends_rotation_and_truncation() -> TestResult {
+    use std::io::{Read, Write};
+    use std::process::Stdio;
+
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("app.log");
+    fs::write(&path, "one\n")?;
+    let mut child = std::process::Command::new(assert_cmd::cargo::cargo_bin("catr"))
+        .args(["-n", "-f"])
+        .arg(&path)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+    let (tx, rx) = std::sync::mpsc::channel();
+    let mut stdout = child.stdout.take().unwrap();
+    std::thread::spawn(move || {
+        let mut buf = [0; 1024];
+        while let Ok(n @ 1..) = stdout.read(&mut buf) {
+            if tx.send(buf[..n].to_vec()).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut seen = Vec::new();
+    wait_for(&rx, &mut seen, "     1\tone\n");
+    let mut log = fs::OpenOptions::new().append(true).open(&path)?;
+    log.write_all(b"tw")?;
+    log.flush()?;
+    std::thread::sleep(std::time::Duration::from_millis(200));
+    log.write_all(b"o\n")?;
+    wait_for(&rx, &mut seen, "     2\ttwo\n");
+
+    // rotated: the last words of the old file come first
+    fs::rename(&path, dir.path().join("app.log.1"))?;
+    log.write_all(b"three\n")?;
+    fs::write(&path, "four\n")?;
+    wait_for(&rx, &mut seen, "     4\tfour\n");
+
+    fs::write(&path, "5\n")?;
+    wait_for(&rx, &mut seen, "     5\t5\n");
+
+    child.kill()?;
+    child.wait()?;
+    assert_eq!(
+        String::from_utf8(seen)?,
+        "     1\tone\n     2\ttwo\n     3\tthree\n     4\tfour\n     5\t5\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn dies_follow_without_single_file() -> TestResult {
+    for args in [&["-f"][..], &["-f", "tests/inputs/fox.txt", "tests/inputs/spiders.txt"][..]] {
+        let mut cmd = Command::cargo_bin("catr")?;
+        cmd.args(args)
+        .assert()
+        .failure()
+        .stderr("--follow requires a single FILE other than -\n");
+    }
+    Ok(())
+}
+
+#[test]
+fn run_with_crlf_to_lf_and_strip_bom() -> TestResult {
+    let mut cmd = Command::cargo_bin("catr")?;
+    cmd.args(["--crlf-to-lf", "--strip-bom", "tests/inputs/dos.txt", "tests/inputs/mixed.txt"])
+    .assert()
+    .success()
+    .stdout("one\ntwo\n\n\nthree\na\nb\nc\rd");
+    Ok(())
+}
+
+#[test]
+fn run_with_lf_to_crlf_and_add_bom() -> TestResult {
+    let mut cmd = Command::cargo_bin("catr")?;
+    cmd.args(["--lf-to-crlf", "--add-bom", "-b", "tests/inputs/dos.txt", "tests/inputs/spiders.txt"])
+    .assert()
+    .success()
+    .stdout("\u{feff}     1\tone\r\n     2\ttwo\r\n\r\n\r\n     3\tthree\r\n     4\tDon't worry, spiders,\r\n     5\tI keep house\r\n     6\tcasually.");
+    Ok(())
+}
+
+#[test]
+fn run_with_crlf_to_lf_before_other_options() -> TestResult {
+    let mut cmd = Command::cargo_bin("catr")?;
+    cmd.args(["-s", "-E", "--crlf-to-lf", "tests/inputs/dos.txt"])
+    .assert()
+    .success()
+    .stdout("\u{feff}one$\ntwo$\n$\nthree$\n");
+    Ok(())
+}
+
+#[test]
+fn run_with_detect() -> TestResult {
+    let mut cmd = Command::cargo_bin("catr")?;
+    cmd.args([
+        "--detect",
+        "tests/inputs/dos.txt",
+        "tests/inputs/mixed.txt",
+        "tests/inputs/the-bustle.txt",
+        "tests/inputs/fox.txt",
+    ])
+    .assert()
+    .success()
+    .stdout(concat!(
+        "tests/inputs/dos.txt: CRLF, BOM\n",
+        "tests/inputs/mixed.txt: mixed (LF 1, CRLF 1, CR 1), no BOM\n",
+        "tests/inputs/the-bustle.txt: LF, no BOM\n",
+        "tests/inputs/fox.txt: none, no BOM\n",
+    ));
+    Ok(())
+}
+
+#[test]
+fn dies_conflicting_line_endings() -> TestResult {
+    let mut cmd = Command::cargo_bin("catr")?;
+    cmd.args(["--crlf-to-lf", "--lf-to-crlf", "tests/inputs/dos.txt"])
+    .assert()
+    .failure()
+    .stderr(predicate::str::contains("cannot be used with"));
+    Ok(())
+}
+
+#[test]
+fn dies_detect_with_encoding() -> TestResult {
+    // the report is about the bytes of the file, not about decoded text
+    for option in ["--from-encoding", "--to-encoding"] {
+        let mut cmd = Command::cargo_bin("catr")?;
+        cmd.args(["--detect", option, "utf-8", "tests/inputs/dos.txt"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot be used with"));
+    }
+    Ok(())
+}
+
+#[test]
+fn run_with_utf16_input_and_number_lines() -> TestResult {
+    let mut cmd = Command::cargo_bin("catr")?;
+    cmd.args(["-n", "--from-encoding", "auto", "tests/inputs/utf16le.txt", "tests/inputs/utf16be.txt"])
+    .assert()
+    .success()
+    .stdout("     1\tone\n     2\ttwo\n     3\t\n     4\tthree ✓\n     5\tone\n     6\ttwo\n");
+    Ok(())
+}
+
+#[test]
+fn run_with_shift_jis_input() -> TestResult {
+    let expected = "名前,都市,人口\n山田太郎,東京,1400万\n佐藤花子,大阪,880万\n";
+    for from in ["shift_jis", "auto"] {
+        let mut cmd = Command::cargo_bin("catr")?;
+        cmd.args(["--from-encoding", from, "tests/inputs/sjis.csv"])
+        .assert()
+        .success()
+        .stdout(expected);
+    }
+    Ok(())
+}
+
+#[test]
+fn run_with_to_encoding() -> TestResult {
+    let expected: Vec<u8> = "     1\t名前,都市,人口\n"
+        .encode_utf16()
+        .flat_map(|unit| unit.to_le_bytes())
+        .collect();
+    let mut cmd = Command::cargo_bin("catr")?;
+    cmd.args(["-n", "--to-encoding", "utf-16le", "-"])
+    .write_stdin(fs::read("tests/inputs/sjis.csv")?[..15].to_vec())
+    .assert()
+    .success()
+    .stdout(expected);
+    Ok(())
+}
+
+#[test]
+fn run_with_on_error() -> TestResult {
+    let mut cmd = Command::cargo_bin("catr")?;
+    cmd.args(["--from-encoding", "utf-8", "--on-error", "replace", "tests/inputs/latin1.txt"])
+    .assert()
+    .success()
+    .stdout(predicate::str::starts_with("caf\u{fffd} na\u{fffd}ve\n"));
+
+    let mut cmd = Command::cargo_bin("catr")?;
+    cmd.args(["--to-encoding", "latin1", "--on-error", "skip", "tests/inputs/utf16le.txt"])
+    .assert()
+    .success()
+    .stdout("one\ntwo\n\nthree \n");
+
+    let mut cmd = Command::cargo_bin("catr")?;
+    cmd.args(["--to-encoding", "latin1", "--on-error", "fail", "tests/inputs/utf16le.txt"])
+    .assert()
+    .failure()
+    .stderr("cannot encode U+2713 in windows-1252\n");
+
+    let mut cmd = Command::cargo_bin("catr")?;
+    cmd.args(["--from-encoding", "utf-8", "--on-error", "fail", "tests/inputs/latin1.txt"])
+    .assert()
+    .failure()
+    .stderr("tests/inputs/latin1.txt: invalid UTF-8 input near byte 4\n");
+    Ok(())
+}
+
+#[test]
+fn dies_unknown_encoding() -> TestResult {
+    let mut cmd = Command::cargo_bin("catr")?;
+    cmd.args(["--to-encoding", "klingon", "tests/inputs/fox.txt"])
+    .assert()
+    .failure()
+    .stderr("unknown encoding -- klingon\n");
+    Ok(())
+}
+
+/// Starts catr on a pipe, handing back the pipe and what catr writes as it
+/// comes.
+fn spawn_on_pipe(args: &[&str]) -> std::io::Result<(std::process::Child, std::sync::mpsc::Receiver<Vec<u8>>)> {
+    use std::io::Read;
+    use std::process::Stdio;
+
+    let mut child = std::process::Command::new(assert_cmd::cargo::cargo_bin("catr"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let (tx, rx) = std::sync::mpsc::channel();
+    let mut stdout = child.stdout.take().unwrap();
+    std::thread::spawn(move || {
+        let mut buf = [0; 1024];
+        while let Ok(n @ 1..) = stdout.read(&mut buf) {
+            if tx.send(buf[..n].to_vec()).is_err() {
+                break;
+            }
+        }
+    });
+    Ok((child, rx))
+}
+
+#[test]
+fn streams_lines_from_a_pipe() -> TestResult {
+    use std::io::Write;
+
+    let (mut child, rx) = spawn_on_pipe(&["--no-decompress", "-n"])?;
+    let mut stdin = child.stdin.take().unwrap();
+    stdin.write_all(b"hi\n")?;
+    stdin.flush()?;
+    // the first line comes out while the pipe is still open
+    let mut seen = Vec::new();
+    wait_for(&rx, &mut seen, "     1\thi\n");
+    stdin.write_all(b"there\n")?;
+    drop(stdin);
+    wait_for(&rx, &mut seen, "     2\tthere\n");
+    assert!(child.wait()?.success());
+    Ok(())
+}
+
+#[test]
+fn streams_a_pipe_while_looking_for_magic_numbers() -> TestResult {
+    use std::io::Write;
+
+    for args in [&[][..], &["-n"][..]] {
+        let (mut child, rx) = spawn_on_pipe(args)?;
+        let mut stdin = child.stdin.take().unwrap();
+        stdin.write_all(b"hi\n")?;
+        stdin.flush()?;
+        let mut seen = Vec::new();
+        wait_for(&rx, &mut seen, "hi\n");
+        drop(stdin);
+        assert!(child.wait()?.success());
+    }
+    Ok(())
+}
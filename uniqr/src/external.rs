@@ -0,0 +1,287 @@
+//! Spill-to-disk deduplication for `--global` once `--memory-limit` is hit.
+//!
+//! Every key and record is given a sequence number and written to one of
+//! `FAN_OUT` partition files chosen by the hash of its key, so all copies of
+//! a key end up in the same file. Each partition is then deduplicated on its
+//! own (and partitioned again with a new hash seed if its distinct keys still
+//! do not fit), and the surviving first occurrences are merged back by
+//! sequence number so the output keeps first-seen order. All temporary files
+//! live in one directory that is removed when `finish` returns, on success or
+//! error.
+
+use super::{Config, MyResult, Occurrence, Output, StoredKey};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use xxhash_rust::xxh3::xxh3_64_with_seed;
+
+/// Rough bookkeeping cost of one distinct key on top of its key and record.
+pub(crate) const ENTRY_OVERHEAD: usize = 64;
+const FAN_OUT: usize = 16;
+const MAX_DEPTH: u64 = 4;
+
+/// One record as stored in a partition or survivor file.
+struct Record {
+    seq: u64,
+    count: u64,
+    key: Vec<u8>,
+    record: Vec<u8>,
+}
+
+/// Moves the in-memory state of `uniq_global` to disk, partitions the rest
+/// of `input` (whose first record is number `seq`) and writes the
+/// deduplicated result. `entries` are indexed by the values of `seen`; when
+/// nothing was buffered their records have already been written and are
+/// skipped here.
+pub(crate) fn finish(
+    seen: HashMap<StoredKey, usize>,
+    mut entries: Vec<Occurrence>,
+    mut seq: u64,
+    mut input: impl BufRead,
+    output: &mut Output<impl Write>,
+    config: &Config,
+    limit: usize,
+) -> MyResult<()> {
+    let buffered = config.count || config.repeated || config.unique || config.keep_last;
+    let dir = match &config.temp_dir {
+        Some(parent) => tempfile::Builder::new().prefix("uniqr").tempdir_in(parent),
+        None => tempfile::Builder::new().prefix("uniqr").tempdir(),
+    }
+    .map_err(|e| format!("{}: {}", config.temp_dir.as_deref().unwrap_or("temporary directory"), e))?;
+    let mut partitions = Partitions::new(dir.path(), "p", 0);
+
+    // partitions must see records in sequence order
+    let mut keys: Vec<(u64, StoredKey, usize)> = seen
+        .into_iter()
+        .map(|(key, i)| (entries[i].seq, key, i))
+        .collect();
+    keys.sort_unstable_by_key(|(seq, _, _)| *seq);
+    for (seq, key, i) in keys {
+        let entry = &mut entries[i];
+        let record = std::mem::take(&mut entry.record);
+        partitions.write(&Record { seq, count: entry.count as u64, key: key.to_bytes(), record })?;
+    }
+    drop(entries);
+    let already_written = if buffered { 0 } else { seq };
+
+    let mut record = Vec::new();
+    loop {
+        let bytes_read = input.read_until(config.terminator, &mut record)?;
+        if bytes_read == 0 {
+            break; // reached EOF
+        }
+        let key = StoredKey::new(config.key_of(&record), config.digest).to_bytes();
+        partitions.write(&Record { seq, count: 1, key, record: std::mem::take(&mut record) })?;
+        seq += 1;
+    }
+
+    let mut survivors = Vec::new();
+    for path in partitions.finish()? {
+        dedup_partition(&path, dir.path(), 1, limit, config.keep_last, &mut survivors)?;
+    }
+
+    // k-way merge of the survivor files by sequence number
+    let mut readers = survivors
+        .iter()
+        .map(|path| Ok(BufReader::new(File::open(path)?)))
+        .collect::<io::Result<Vec<_>>>()?;
+    let mut heads: Vec<Option<Record>> = Vec::with_capacity(readers.len());
+    let mut heap = BinaryHeap::new();
+    for (i, reader) in readers.iter_mut().enumerate() {
+        let head = read_record(reader)?;
+        if let Some(record) = &head {
+            heap.push(Reverse((record.seq, i)));
+        }
+        heads.push(head);
+    }
+    while let Some(Reverse((_, i))) = heap.pop() {
+        let record = heads[i].take().unwrap();
+        if record.seq >= already_written && config.selects(record.count as usize) {
+            output.record(config.count.then_some(record.count as usize), &record.record)?;
+        }
+        heads[i] = read_record(&mut readers[i])?;
+        if let Some(next) = &heads[i] {
+            heap.push(Reverse((next.seq, i)));
+        }
+    }
+    output.flush()?;
+    Ok(())
+}
+
+/// Keeps the first (or with `keep_last` the last) record of every key in
+/// the partition at `path`, summing counts, and adds the resulting survivor
+/// file(s) to `survivors`. Records arrive in sequence order, so the first
+/// one seen is the first occurrence.
+fn dedup_partition(
+    path: &Path,
+    dir: &Path,
+    depth: u64,
+    limit: usize,
+    keep_last: bool,
+    survivors: &mut Vec<PathBuf>,
+) -> MyResult<()> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut index: HashMap<Vec<u8>, usize> = HashMap::new();
+    let mut kept: Vec<Record> = Vec::new();
+    let mut memory: usize = 0;
+
+    while let Some(record) = read_record(&mut reader)? {
+        if let Some(&i) = index.get(&record.key) {
+            kept[i].count += record.count;
+            if keep_last {
+                kept[i].seq = record.seq;
+                kept[i].record = record.record;
+            }
+            continue;
+        }
+        memory += record.key.len() + record.record.len() + ENTRY_OVERHEAD;
+        if memory > limit && depth < MAX_DEPTH {
+            drop(index);
+            drop(kept);
+            return repartition(path, dir, depth, limit, keep_last, survivors);
+        }
+        index.insert(record.key.clone(), kept.len());
+        kept.push(Record { key: Vec::new(), ..record });
+    }
+    if keep_last {
+        kept.sort_unstable_by_key(|record| record.seq);
+    }
+
+    let survivor = path.with_extension("s");
+    let mut writer = BufWriter::new(File::create(&survivor)?);
+    for record in &kept {
+        write_record(&mut writer, record)?;
+    }
+    writer.flush()?;
+    fs::remove_file(path)?;
+    survivors.push(survivor);
+    Ok(())
+}
+
+/// Splits a partition whose distinct keys exceed the limit into `FAN_OUT`
+/// smaller ones, using the depth as hash seed so the keys spread out again.
+fn repartition(
+    path: &Path,
+    dir: &Path,
+    depth: u64,
+    limit: usize,
+    keep_last: bool,
+    survivors: &mut Vec<PathBuf>,
+) -> MyResult<()> {
+    let name = path.file_name().unwrap().to_string_lossy().into_owned();
+    let mut partitions = Partitions::new(dir, &name, depth);
+    let mut reader = BufReader::new(File::open(path)?);
+    while let Some(record) = read_record(&mut reader)? {
+        partitions.write(&record)?;
+    }
+    fs::remove_file(path)?;
+
+    for sub in partitions.finish()? {
+        dedup_partition(&sub, dir, depth + 1, limit, keep_last, survivors)?;
+    }
+    Ok(())
+}
+
+/// A set of partition files, opened lazily so empty ones are never created.
+struct Partitions {
+    paths: Vec<PathBuf>,
+    writers: Vec<Option<BufWriter<File>>>,
+    seed: u64,
+}
+
+impl Partitions {
+    fn new(dir: &Path, name: &str, seed: u64) -> Self {
+        Partitions {
+            paths: (0..FAN_OUT).map(|i| dir.join(format!("{}-{}", name, i))).collect(),
+            writers: (0..FAN_OUT).map(|_| None).collect(),
+            seed,
+        }
+    }
+
+    fn write(&mut self, record: &Record) -> io::Result<()> {
+        let i = (xxh3_64_with_seed(&record.key, self.seed) % FAN_OUT as u64) as usize;
+        if self.writers[i].is_none() {
+            self.writers[i] = Some(BufWriter::new(File::create(&self.paths[i])?));
+        }
+        write_record(self.writers[i].as_mut().unwrap(), record)
+    }
+
+    /// Flushes every partition and returns the paths of the non-empty ones.
+    fn finish(self) -> io::Result<Vec<PathBuf>> {
+        let mut paths = Vec::new();
+        for (path, writer) in self.paths.into_iter().zip(self.writers) {
+            if let Some(mut writer) = writer {
+                writer.flush()?;
+                paths.push(path);
+            }
+        }
+        Ok(paths)
+    }
+}
+
+// -------------------- helper functions --------------------
+fn write_record(writer: &mut impl Write, record: &Record) -> io::Result<()> {
+    writer.write_all(&record.seq.to_le_bytes())?;
+    writer.write_all(&record.count.to_le_bytes())?;
+    writer.write_all(&(record.key.len() as u64).to_le_bytes())?;
+    writer.write_all(&record.key)?;
+    writer.write_all(&(record.record.len() as u64).to_le_bytes())?;
+    writer.write_all(&record.record)
+}
+
+fn read_record(reader: &mut impl Read) -> io::Result<Option<Record>> {
+    let mut buf = [0; 8];
+    match reader.read_exact(&mut buf) {
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        result => result?,
+    }
+    let seq = u64::from_le_bytes(buf);
+    let count = read_u64(reader)?;
+    let key = read_bytes(reader)?;
+    let record = read_bytes(reader)?;
+    Ok(Some(Record { seq, count, key, record }))
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn read_bytes(reader: &mut impl Read) -> io::Result<Vec<u8>> {
+    let len = read_u64(reader)? as usize;
+    let mut bytes = vec![0; len];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+// -------------------- tests --------------------
+#[cfg(test)]
+mod tests {
+    use super::{read_record, write_record, Record};
+    use std::io::Cursor;
+
+    #[test]
+    fn test_record_round_trip() {
+        let mut buf = Vec::new();
+        let record = Record { seq: 7, count: 3, key: b"key".to_vec(), record: b"\xffline\n".to_vec() };
+        write_record(&mut buf, &record).unwrap();
+        write_record(&mut buf, &Record { seq: 8, count: 1, key: Vec::new(), record: Vec::new() }).unwrap();
+
+        let mut reader = Cursor::new(buf);
+        let first = read_record(&mut reader).unwrap().unwrap();
+        assert_eq!((first.seq, first.count), (7, 3));
+        assert_eq!((first.key.as_slice(), first.record.as_slice()), (&b"key"[..], &b"\xffline\n"[..]));
+        let second = read_record(&mut reader).unwrap().unwrap();
+        assert_eq!((second.seq, second.key.len(), second.record.len()), (8, 0, 0));
+        assert!(read_record(&mut reader).unwrap().is_none());
+
+        // a record cut short is an error, not a clean EOF
+        let mut buf = Vec::new();
+        write_record(&mut buf, &record).unwrap();
+        buf.truncate(buf.len() - 2);
+        assert!(read_record(&mut Cursor::new(buf)).is_err());
+    }
+}
@@ -0,0 +1,138 @@
+//! Near-duplicate detection for `--fuzzy` and the `--mask` key filter.
+
+use regex::bytes::{Captures, Regex};
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::sync::LazyLock;
+
+/// How `--fuzzy` scores two keys, from 0.0 (nothing alike) to 1.0 (equal).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Metric {
+    /// `1 - edit distance / length of the longer key`, over characters.
+    Levenshtein,
+    /// Shared whitespace-separated tokens over all distinct tokens.
+    Jaccard,
+}
+
+/// Settings of `--fuzzy`: keys at least `threshold` similar are duplicates.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fuzzy {
+    pub metric: Metric,
+    pub threshold: f64,
+}
+
+impl Fuzzy {
+    pub fn matches(&self, a: &str, b: &str) -> bool {
+        a == b || similarity(self.metric, a, b) >= self.threshold
+    }
+}
+
+pub fn similarity(metric: Metric, a: &str, b: &str) -> f64 {
+    match metric {
+        Metric::Levenshtein => {
+            let longest = a.chars().count().max(b.chars().count());
+            if longest == 0 {
+                return 1.0;
+            }
+            1.0 - levenshtein(a, b) as f64 / longest as f64
+        }
+        Metric::Jaccard => {
+            let a: HashSet<&str> = a.split_whitespace().collect();
+            let b: HashSet<&str> = b.split_whitespace().collect();
+            let union = a.union(&b).count();
+            if union == 0 {
+                return 1.0;
+            }
+            a.intersection(&b).count() as f64 / union as f64
+        }
+    }
+}
+
+/// Edit distance in characters, keeping only two rows of the table.
+pub fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+
+    for (i, ca) in a.chars().enumerate() {
+        current[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+    previous[b.len()]
+}
+
+static VOLATILE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(concat!(
+        r"(?P<uuid>\b[0-9a-fA-F]{8}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{12}\b)",
+        r"|(?P<hex>\b0[xX][0-9a-fA-F]+\b|\b[0-9a-fA-F]{8,}\b)",
+        r"|(?P<num>\d+(?:\.\d+)?)",
+    ))
+    .unwrap()
+});
+
+/// Replaces UUIDs, hex values (`0x…` or 8+ hex digits) and decimal numbers
+/// with `<UUID>`, `<HEX>` and `<NUM>`, so lines differing only in ids,
+/// addresses or timings compare equal.
+pub fn mask(key: &[u8]) -> Cow<'_, [u8]> {
+    VOLATILE.replace_all(key, |caps: &Captures| -> &[u8] {
+        if caps.name("uuid").is_some() {
+            b"<UUID>"
+        } else if caps.name("hex").is_some_and(|m| !m.as_bytes().iter().all(u8::is_ascii_digit)) {
+            b"<HEX>"
+        } else {
+            b"<NUM>"
+        }
+    })
+}
+
+// -------------------- tests --------------------
+#[cfg(test)]
+mod tests {
+    use super::{levenshtein, mask, similarity, Fuzzy, Metric};
+
+    #[test]
+    fn test_levenshtein() {
+        assert_eq!(levenshtein("", ""), 0);
+        assert_eq!(levenshtein("abc", ""), 3);
+        assert_eq!(levenshtein("kitten", "sitting"), 3);
+        assert_eq!(levenshtein("flaw", "lawn"), 2);
+        // characters, not bytes
+        assert_eq!(levenshtein("café", "cafe"), 1);
+    }
+
+    #[test]
+    fn test_similarity() {
+        assert_eq!(similarity(Metric::Levenshtein, "", ""), 1.0);
+        assert_eq!(similarity(Metric::Levenshtein, "abcd", "abce"), 0.75);
+        assert_eq!(similarity(Metric::Jaccard, "a b c", "c b a a"), 1.0);
+        assert_eq!(similarity(Metric::Jaccard, "a b c", "a b d"), 0.5);
+        assert_eq!(similarity(Metric::Jaccard, "", "a"), 0.0);
+    }
+
+    #[test]
+    fn test_fuzzy_matches() {
+        let fuzzy = Fuzzy { metric: Metric::Levenshtein, threshold: 0.9 };
+        assert!(fuzzy.matches("timeout after 1203 ms", "timeout after 1207 ms"));
+        assert!(!fuzzy.matches("timeout after 1203 ms", "connection refused"));
+
+        let fuzzy = Fuzzy { metric: Metric::Jaccard, threshold: 0.5 };
+        assert!(fuzzy.matches("user bob failed login", "user alice failed login"));
+    }
+
+    #[test]
+    fn test_mask() {
+        assert_eq!(mask(b"no digits"), &b"no digits"[..]);
+        assert_eq!(mask(b"took 12 ms (0.53s)"), &b"took <NUM> ms (<NUM>s)"[..]);
+        assert_eq!(mask(b"at 0x7ffd5e8a and deadbeef00"), &b"at <HEX> and <HEX>"[..]);
+        assert_eq!(mask(b"id 123456789"), &b"id <NUM>"[..]);
+        assert_eq!(
+            mask(b"req 550e8400-e29b-41d4-a716-446655440000 done"),
+            &b"req <UUID> done"[..]
+        );
+        assert_eq!(mask(b"\xff 42 \xfe"), &b"\xff <NUM> \xfe"[..]);
+    }
+}
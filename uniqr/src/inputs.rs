@@ -0,0 +1,227 @@
+//! Readers between the input files and `uniq`: several files as one
+//! stream for `--inputs` and `--files0-from`, and a record filter for
+//! `--ignore-blank-lines` and `--on-invalid`.
+//!
+//! With `--with-filename` every record is tagged with the index of the file
+//! it came from, written as `TAG_LEN` hex digits in front of it. Hex digits
+//! are never a terminator, so the tag survives `read_until` and spilling to
+//! disk unchanged; `Config::key_of` leaves it out of the comparison and
+//! `Output` replaces it with the file name.
+
+use super::{open, MyResult};
+use std::io::{self, BufRead, Read, Write};
+
+pub(crate) const TAG_LEN: usize = 8;
+
+/// The files `names[start..end]` read one after the other. A file whose last
+/// record lacks a terminator gets one, so it does not run into the first
+/// record of the next file, unless it is the last of all `names`.
+pub(crate) struct Inputs<'a> {
+    names: &'a [String],
+    /// index of the file being read
+    current: usize,
+    end: usize,
+    reader: Option<Box<dyn BufRead>>,
+    terminator: u8,
+    tagged: bool,
+    /// the record being handed out, and how much of it was consumed
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl<'a> Inputs<'a> {
+    /// Opens the first file right away, so a missing input is reported
+    /// before anything is written.
+    pub(crate) fn new(names: &'a [String], start: usize, end: usize, terminator: u8, tagged: bool) -> MyResult<Self> {
+        let reader = match names.get(start) {
+            Some(name) if start < end => Some(open(name).map_err(|e| format!("{}: {}", name, e))?),
+            _ => None,
+        };
+        Ok(Inputs { names, current: start, end, reader, terminator, tagged, buf: Vec::new(), pos: 0 })
+    }
+
+    /// Reads the next record into `buf`; leaves it empty at the end of all files.
+    fn next_record(&mut self) -> io::Result<()> {
+        self.buf.clear();
+        self.pos = 0;
+        while let Some(reader) = &mut self.reader {
+            if self.tagged {
+                write!(self.buf, "{:0width$x}", self.current, width = TAG_LEN)?;
+            }
+            let name = &self.names[self.current];
+            let bytes_read = reader
+                .read_until(self.terminator, &mut self.buf)
+                .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", name, e)))?;
+            if bytes_read > 0 {
+                if self.buf.last() != Some(&self.terminator) && self.current + 1 < self.names.len() {
+                    self.buf.push(self.terminator);
+                }
+                return Ok(());
+            }
+
+            // reached EOF, move on to the next file
+            self.buf.clear();
+            self.current += 1;
+            self.reader = match self.names.get(self.current) {
+                Some(name) if self.current < self.end => Some(
+                    open(name).map_err(|e| io::Error::other(format!("{}: {}", name, e)))?,
+                ),
+                _ => None,
+            };
+        }
+        Ok(())
+    }
+}
+
+impl Read for Inputs<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl BufRead for Inputs<'_> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos == self.buf.len() {
+            self.next_record()?;
+        }
+        Ok(&self.buf[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos += amt;
+    }
+}
+
+/// The records of `inner` that `keep` accepts. `keep` sees every record
+/// without its terminator (and tag, for `tagged` records) and may also fail
+/// the read.
+pub(crate) struct Filter<R, F> {
+    inner: R,
+    terminator: u8,
+    tagged: bool,
+    keep: F,
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl<R: BufRead, F: FnMut(&[u8]) -> io::Result<bool>> Filter<R, F> {
+    pub(crate) fn new(inner: R, terminator: u8, tagged: bool, keep: F) -> Self {
+        Filter { inner, terminator, tagged, keep, buf: Vec::new(), pos: 0 }
+    }
+}
+
+impl<R: BufRead, F: FnMut(&[u8]) -> io::Result<bool>> Read for Filter<R, F> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<R: BufRead, F: FnMut(&[u8]) -> io::Result<bool>> BufRead for Filter<R, F> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        while self.pos == self.buf.len() {
+            self.buf.clear();
+            self.pos = 0;
+            if self.inner.read_until(self.terminator, &mut self.buf)? == 0 {
+                break; // reached EOF
+            }
+            let record = if self.tagged { untag(&self.buf).1 } else { &self.buf };
+            let record = record.strip_suffix(&[self.terminator]).unwrap_or(record);
+            if !(self.keep)(record)? {
+                self.buf.clear();
+            }
+        }
+        Ok(&self.buf[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos += amt;
+    }
+}
+
+/// Splits the tag off a record read with `--with-filename`, returning the
+/// file index and the record itself.
+pub(crate) fn untag(record: &[u8]) -> (usize, &[u8]) {
+    let (tag, rest) = record.split_at(TAG_LEN.min(record.len()));
+    let index = std::str::from_utf8(tag)
+        .ok()
+        .and_then(|tag| usize::from_str_radix(tag, 16).ok())
+        .unwrap_or(0);
+    (index, rest)
+}
+
+/// Parses the NUL-separated file names of `--files0-from`.
+pub(crate) fn parse_files0(source: &str, list: &[u8]) -> MyResult<Vec<String>> {
+    let list = list.strip_suffix(b"\0").unwrap_or(list);
+    if list.is_empty() {
+        return Ok(Vec::new());
+    }
+    list.split(|&b| b == 0)
+        .map(|name| match name {
+            b"" => Err(format!("{}: invalid zero-length file name", source).into()),
+            b"-" if source == "-" => {
+                Err("file operand '-' conflicts with --files0-from reading standard input".into())
+            }
+            _ => Ok(String::from_utf8_lossy(name).into_owned()),
+        })
+        .collect()
+}
+
+// -------------------- tests --------------------
+#[cfg(test)]
+mod tests {
+    use super::{parse_files0, untag, Inputs};
+    use std::fs;
+    use std::io::Read;
+
+    #[test]
+    fn test_inputs() {
+        let dir = tempfile::tempdir().unwrap();
+        let names: Vec<String> = [("a", "1\n2"), ("empty", ""), ("b", "3\n4")]
+            .iter()
+            .map(|(name, text)| {
+                let path = dir.path().join(name);
+                fs::write(&path, text).unwrap();
+                path.to_str().unwrap().to_string()
+            })
+            .collect();
+
+        let mut text = String::new();
+        Inputs::new(&names, 0, 3, b'\n', false).unwrap().read_to_string(&mut text).unwrap();
+        assert_eq!(text, "1\n2\n3\n4");
+
+        // a file read on its own is terminated if more files follow
+        let mut text = String::new();
+        Inputs::new(&names, 0, 1, b'\n', true).unwrap().read_to_string(&mut text).unwrap();
+        assert_eq!(text, "000000001\n000000002\n");
+
+        let mut text = String::new();
+        Inputs::new(&names, 2, 3, b'\n', true).unwrap().read_to_string(&mut text).unwrap();
+        assert_eq!(text, "000000023\n000000024");
+
+        let missing = vec!["tests/inputs/missing.txt".to_string()];
+        assert!(Inputs::new(&missing, 0, 1, b'\n', false).is_err());
+    }
+
+    #[test]
+    fn test_untag() {
+        assert_eq!(untag(b"0000000ahello\n"), (10, &b"hello\n"[..]));
+        assert_eq!(untag(b"00000000"), (0, &b""[..]));
+    }
+
+    #[test]
+    fn test_parse_files0() {
+        assert_eq!(parse_files0("list", b"a\0b c\0").unwrap(), vec!["a", "b c"]);
+        assert_eq!(parse_files0("list", b"a\0-").unwrap(), vec!["a", "-"]);
+        assert!(parse_files0("list", b"").unwrap().is_empty());
+        assert!(parse_files0("list", b"a\0\0b").is_err());
+        assert!(parse_files0("-", b"a\0-\0").is_err());
+    }
+}
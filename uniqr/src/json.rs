@@ -0,0 +1,163 @@
+//! Keys taken from JSON lines, for `--json-key` and `--json-canonical`.
+//!
+//! Values are compared in a canonical form: object keys sorted and no
+//! whitespace, so documents that differ only in layout or key order match.
+
+use serde_json::Value;
+use std::fmt;
+use std::io::Write;
+
+/// A path into a JSON value such as `.user.id` or `.items[0].sku`; `.`
+/// alone is the whole value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JsonPath(Vec<Step>);
+
+#[derive(Debug, Clone, PartialEq)]
+enum Step {
+    Field(String),
+    Index(usize),
+}
+
+impl JsonPath {
+    pub fn parse(path: &str) -> Result<Self, String> {
+        let invalid = || format!("invalid JSON key -- {}", path);
+        let mut steps = Vec::new();
+        let mut rest = path.strip_prefix('.').ok_or_else(invalid)?;
+        while !rest.is_empty() {
+            if let Some(after) = rest.strip_prefix('[') {
+                let (index, after) = after.split_once(']').ok_or_else(invalid)?;
+                steps.push(Step::Index(index.parse().map_err(|_| invalid())?));
+                rest = after.strip_prefix('.').filter(|after| !after.is_empty()).unwrap_or(after);
+            } else {
+                let end = rest.find(['.', '[']).unwrap_or(rest.len());
+                if end == 0 {
+                    return Err(invalid());
+                }
+                steps.push(Step::Field(rest[..end].to_string()));
+                rest = &rest[end..];
+                if let Some(after) = rest.strip_prefix('.') {
+                    if after.is_empty() {
+                        return Err(invalid());
+                    }
+                    rest = after;
+                }
+            }
+        }
+        Ok(JsonPath(steps))
+    }
+
+    /// The value at this path, if there is one.
+    pub fn get<'a>(&self, value: &'a Value) -> Option<&'a Value> {
+        self.0.iter().try_fold(value, |value, step| match step {
+            Step::Field(name) => value.get(name),
+            Step::Index(i) => value.get(i),
+        })
+    }
+}
+
+impl fmt::Display for JsonPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !matches!(self.0.first(), Some(Step::Field(_))) {
+            write!(f, ".")?;
+        }
+        for step in &self.0 {
+            match step {
+                Step::Field(name) => write!(f, ".{}", name)?,
+                Step::Index(i) => write!(f, "[{}]", i)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The key of a JSON `record`: the canonical form of the value at every
+/// path, separated by NUL bytes (which canonical JSON never contains), with
+/// nothing for a missing value. `None` if `record` is not valid JSON.
+pub fn key(record: &[u8], paths: &[JsonPath]) -> Option<Vec<u8>> {
+    let value: Value = serde_json::from_slice(record).ok()?;
+    let mut key = Vec::new();
+    for (i, path) in paths.iter().enumerate() {
+        if i > 0 {
+            key.push(0);
+        }
+        if let Some(value) = path.get(&value) {
+            write_canonical(&mut key, value);
+        }
+    }
+    Some(key)
+}
+
+/// Whether `record` is valid JSON.
+pub fn is_valid(record: &[u8]) -> bool {
+    serde_json::from_slice::<Value>(record).is_ok()
+}
+
+/// Writes `value` with sorted object keys and without whitespace.
+pub fn write_canonical(out: &mut Vec<u8>, value: &Value) {
+    match value {
+        Value::Array(items) => {
+            out.push(b'[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(b',');
+                }
+                write_canonical(out, item);
+            }
+            out.push(b']');
+        }
+        Value::Object(map) => {
+            let mut entries: Vec<_> = map.iter().collect();
+            entries.sort_unstable_by_key(|(name, _)| *name);
+            out.push(b'{');
+            for (i, (name, item)) in entries.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(b',');
+                }
+                write_canonical(out, &Value::String(name.clone()));
+                out.push(b':');
+                write_canonical(out, item);
+            }
+            out.push(b'}');
+        }
+        // scalars have only one compact form
+        _ => write!(out, "{}", value).unwrap(),
+    }
+}
+
+// -------------------- tests --------------------
+#[cfg(test)]
+mod tests {
+    use super::{key, JsonPath};
+
+    fn paths(paths: &[&str]) -> Vec<JsonPath> {
+        paths.iter().map(|path| JsonPath::parse(path).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_parse_path() {
+        for path in [".", ".user", ".user.id", ".items[0].sku", ".[1][2]", ".a[0]"] {
+            assert_eq!(JsonPath::parse(path).unwrap().to_string(), path);
+        }
+        for path in ["", "user", ".user.", "..a", ".a[x]", ".a[0"] {
+            assert_eq!(JsonPath::parse(path).unwrap_err(), format!("invalid JSON key -- {}", path));
+        }
+    }
+
+    #[test]
+    fn test_key() {
+        let record = br#"{"user": {"id": 7, "name": "bob"}, "tags": ["a", {"y": 1, "x": 2}]}"#;
+        assert_eq!(key(record, &paths(&[".user.id"])).unwrap(), b"7");
+        assert_eq!(key(record, &paths(&[".user.name", ".tags[1]"])).unwrap(), b"\"bob\"\0{\"x\":2,\"y\":1}");
+        // missing values are empty, unlike null
+        assert_eq!(key(record, &paths(&[".nope", ".tags[5]"])).unwrap(), b"\0");
+        assert_eq!(key(b"{\"nope\": null}", &paths(&[".nope"])).unwrap(), b"null");
+
+        // layout and key order do not matter for the whole value either
+        let a = key(r#"{"b": [1, 2], "a": {"d": "é", "c": null}}"#.as_bytes(), &paths(&["."]));
+        let b = key(r#"{"a":{"c":null,"d":"é"},"b":[1,2]}"#.as_bytes(), &paths(&["."]));
+        assert_eq!(a, b);
+        assert_eq!(a.unwrap(), r#"{"a":{"c":null,"d":"é"},"b":[1,2]}"#.as_bytes());
+
+        assert_eq!(key(b"{\"a\": ", &paths(&[".a"])), None);
+    }
+}
@@ -1,15 +1,219 @@
-use clap::{Arg, Command, ArgAction};
-// use std::fs::File;
-// use std::io::{self, BufRead, BufReader};
+use clap::{Arg, ArgGroup, Command, ArgAction, value_parser};
+use regex::bytes::Regex;
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::hash_map::{Entry, HashMap};
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
 use std::error::Error;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+use chrono::{DateTime, TimeDelta, Utc};
+use unicode_normalization::UnicodeNormalization;
+
+mod external;
+mod inputs;
+pub mod json;
+mod merge;
+mod out_file;
+pub mod fuzzy;
+pub mod sketch;
+pub mod timestamp;
+
+use fuzzy::{Fuzzy, Metric};
+use inputs::{Filter, Inputs};
+use json::JsonPath;
+use merge::Merge;
+use out_file::OutFile;
+use sketch::{BloomFilter, HyperLogLog};
+use timestamp::TimeFormat;
 
 pub type MyResult<T> = Result<T, Box<dyn Error>>;
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Config {
-    in_file: String,
-    out_file: Option<String>,
-    count: bool,
+    pub in_file: String,
+    /// `--inputs`/`--files0-from`, read in place of `in_file` if not empty
+    pub inputs: Vec<String>,
+    pub per_file: bool,
+    pub merge: bool,
+    /// drop records that are empty or all whitespace before comparing
+    pub ignore_blank_lines: bool,
+    /// what to do with lines that are not JSON under a JSON key
+    pub on_invalid: OnInvalid,
+    /// prefix output records with their file name; `run` then tags the
+    /// records it passes to `uniq`, see the `inputs` module
+    pub with_filename: bool,
+    pub out_file: Option<String>,
+    pub append: bool,
+    pub count: bool,
+    pub repeated: bool,
+    pub unique: bool,
+    pub all_repeated: Option<Delimit>,
+    pub group: Option<Group>,
+    pub key: KeySpec,
+    pub global: bool,
+    pub digest: bool,
+    pub memory_limit: Option<usize>,
+    pub temp_dir: Option<String>,
+    pub estimate: Option<u8>,
+    pub bloom: Option<BloomSpec>,
+    pub histogram: Option<Histogram>,
+    pub keep_last: bool,
+    pub syslog: bool,
+    pub summary_format: String,
+    pub window: Option<Duration>,
+    /// 1-based blank-separated field holding the event time for `--window`
+    pub time_field: Option<usize>,
+    pub time_format: TimeFormat,
+    pub fuzzy: Option<Fuzzy>,
+    pub terminator: u8,
+}
+
+/// Sizing of the `--bloom` filter.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BloomSpec {
+    pub fp_rate: f64,
+    pub expected_items: usize,
+}
+
+/// Settings of `--histogram`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Histogram {
+    pub format: Format,
+    /// only the `--top` most frequent keys
+    pub top: Option<usize>,
+}
+
+/// How `--histogram` writes its table.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Text,
+    Csv,
+    Json,
+}
+
+/// Which part of a line takes part in the comparison (`-f`, `-s`, `-w`, `-i`).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct KeySpec {
+    pub strategy: KeyStrategy,
+    pub skip_fields: usize,
+    pub skip_chars: usize,
+    pub check_chars: Option<usize>,
+    pub ignore_case: bool,
+    pub mask: bool,
+    pub normalize: Option<Normalization>,
+    pub ignore_trailing_space: bool,
+    pub squeeze_space: bool,
+}
+
+/// What `--on-invalid` does with lines that do not parse as JSON.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OnInvalid {
+    /// stop with the number of the line
+    Error,
+    /// drop the line
+    Skip,
+    /// keep the line and compare it as plain text
+    Passthrough,
+}
+
+/// The Unicode normalization form of `--normalize`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Normalization {
+    /// canonical composition: precomposed and combining accents compare equal
+    Nfc,
+    /// compatibility composition: also ligatures, full-width forms and the like
+    Nfkc,
+}
+
+/// Where the key comes from before `-s`, `-w` and `-i` are applied.
+#[derive(Debug, Clone, Default)]
+pub enum KeyStrategy {
+    /// The line after `-f` blank-separated fields (the default).
+    #[default]
+    Fields,
+    /// The 1-based `--key` column split on `--delimiter`; empty if missing.
+    Column { index: usize, delimiter: String },
+    /// The first capture group of `--key-regex` (or the whole match when the
+    /// pattern has no groups); lines that do not match are keyed as a whole.
+    Regex(Regex),
+    /// The canonical JSON of the values at `--json-key` paths, or of the
+    /// whole line with `--json-canonical`; invalid JSON is keyed as a whole.
+    Json(Vec<JsonPath>),
+}
+
+impl PartialEq for KeyStrategy {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (KeyStrategy::Fields, KeyStrategy::Fields) => true,
+            (KeyStrategy::Column { index: i, delimiter: d }, KeyStrategy::Column { index: j, delimiter: e }) => {
+                i == j && d == e
+            }
+            (KeyStrategy::Regex(a), KeyStrategy::Regex(b)) => a.as_str() == b.as_str(),
+            (KeyStrategy::Json(a), KeyStrategy::Json(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+/// How `-D`/`--all-repeated` delimits the groups of repeated lines.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Delimit {
+    None,
+    Prepend,
+    Separate,
+}
+
+/// Where `--group` puts the empty line between groups.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Group {
+    Separate,
+    Prepend,
+    Append,
+    Both,
+}
+
+/// Summary written by `--syslog` when a run of repeated lines ends.
+pub const DEFAULT_SUMMARY_FORMAT: &str = "last message repeated {count} times";
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            in_file: "-".to_string(),
+            inputs: Vec::new(),
+            per_file: false,
+            merge: false,
+            ignore_blank_lines: false,
+            on_invalid: OnInvalid::Error,
+            with_filename: false,
+            out_file: None,
+            append: false,
+            count: false,
+            repeated: false,
+            unique: false,
+            all_repeated: None,
+            group: None,
+            key: KeySpec::default(),
+            global: false,
+            digest: false,
+            memory_limit: None,
+            temp_dir: None,
+            estimate: None,
+            bloom: None,
+            histogram: None,
+            keep_last: false,
+            syslog: false,
+            summary_format: DEFAULT_SUMMARY_FORMAT.to_string(),
+            window: None,
+            time_field: None,
+            time_format: TimeFormat::Rfc3339,
+            fuzzy: None,
+            terminator: b'\n',
+        }
+    }
 }
 
 pub fn get_args() -> MyResult<Config> {
@@ -31,6 +235,62 @@ pub fn get_args() -> MyResult<Config> {
             // .required(false)
             // .default_value("-"),
         )
+        .arg(
+            Arg::new("output")
+            .short('o')
+            .long("output")
+            .value_name("OUT_FILE")
+            .help("Output file, also with --inputs or --files0-from")
+            .conflicts_with("out_file"),
+        )
+        .group(
+            ArgGroup::new("out")
+            .args(["out_file", "output"]),
+        )
+        .arg(
+            Arg::new("inputs")
+            .long("inputs")
+            .value_name("FILE")
+            .help("Read the FILEs as one stream instead of IN_FILE")
+            .num_args(1..)
+            .conflicts_with_all(["in_file", "files0_from"]),
+        )
+        .arg(
+            Arg::new("files0_from")
+            .long("files0-from")
+            .value_name("F")
+            .help("Read input files from the NUL-terminated names in file F (- for stdin)")
+            .conflicts_with("in_file"),
+        )
+        .arg(
+            Arg::new("per_file")
+            .long("per-file")
+            .help("Start afresh at every input file")
+            .action(ArgAction::SetTrue)
+            // one table or estimate per file would have no file to tell them apart
+            .conflicts_with_all(["histogram", "estimate"]),
+        )
+        .arg(
+            Arg::new("merge")
+            .long("merge")
+            .help("Merge inputs already sorted by key, failing on one out of order")
+            .action(ArgAction::SetTrue)
+            .conflicts_with_all(["per_file", "fuzzy"]),
+        )
+        .arg(
+            Arg::new("with_filename")
+            .short('H')
+            .long("with-filename")
+            .help("Prefix each output line with the name of its input file")
+            .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("append")
+            .long("append")
+            .help("Append to OUT_FILE instead of replacing it")
+            .action(ArgAction::SetTrue)
+            .requires("out"),
+        )
         .arg(
             Arg::new("count")
             .short('c')
@@ -38,16 +298,2104 @@ pub fn get_args() -> MyResult<Config> {
             .help("Count occurrences")
             .action(ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("repeated")
+            .short('d')
+            .long("repeated")
+            .help("Only print duplicate lines, one for each group")
+            .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("unique")
+            .short('u')
+            .long("unique")
+            .help("Only print unique lines")
+            .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("all_repeated")
+            .short('D')
+            .long("all-repeated")
+            .value_name("METHOD")
+            .help("Print all duplicate lines, delimiting groups with METHOD")
+            .num_args(0..=1)
+            .require_equals(true)
+            .default_missing_value("none")
+            .value_parser(["none", "prepend", "separate"])
+            .conflicts_with("count"),
+        )
+        .arg(
+            Arg::new("group")
+            .long("group")
+            .value_name("METHOD")
+            .help("Show all lines, separating each group with an empty line")
+            .num_args(0..=1)
+            .require_equals(true)
+            .default_missing_value("separate")
+            .value_parser(["separate", "prepend", "append", "both"])
+            .conflicts_with_all(["count", "repeated", "unique", "all_repeated"]),
+        )
+        .arg(
+            Arg::new("zero_terminated")
+            .short('z')
+            .long("zero-terminated")
+            .help("Line delimiter is NUL, not newline")
+            .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("skip_fields")
+            .short('f')
+            .long("skip-fields")
+            .value_name("N")
+            .help("Avoid comparing the first N fields")
+            .value_parser(value_parser!(usize))
+            .default_value("0"),
+        )
+        .arg(
+            Arg::new("key")
+            .short('k')
+            .long("key")
+            .value_name("N")
+            .help("Compare only column N, split on --delimiter")
+            .value_parser(value_parser!(u64).range(1..))
+            .conflicts_with_all(["skip_fields", "key_regex"]),
+        )
+        .arg(
+            Arg::new("delimiter")
+            .short('t')
+            .long("delimiter")
+            .value_name("DELIM")
+            .help("Column delimiter for --key")
+            .default_value("\t")
+            .requires("key"),
+        )
+        .arg(
+            Arg::new("key_regex")
+            .long("key-regex")
+            .value_name("REGEX")
+            .help("Compare only the first capture group of REGEX")
+            .conflicts_with("skip_fields"),
+        )
+        .arg(
+            Arg::new("json_key")
+            .long("json-key")
+            .value_name("PATH")
+            .help("Parse lines as JSON and compare the value at PATH, e.g. .user.id (repeatable)")
+            .action(ArgAction::Append)
+            .conflicts_with_all(["skip_fields", "key", "key_regex"]),
+        )
+        .arg(
+            Arg::new("json_canonical")
+            .long("json-canonical")
+            .help("Parse lines as JSON and compare them ignoring key order and whitespace")
+            .action(ArgAction::SetTrue)
+            .conflicts_with_all(["skip_fields", "key", "key_regex", "json_key"]),
+        )
+        .arg(
+            Arg::new("on_invalid")
+            .long("on-invalid")
+            .value_name("POLICY")
+            .help("What to do with lines that are not JSON")
+            .default_value("error")
+            .value_parser(["error", "skip", "passthrough"]),
+        )
+        .arg(
+            Arg::new("keep_last")
+            .long("keep-last")
+            .help("Print the last line of every group instead of the first")
+            .action(ArgAction::SetTrue)
+            .conflicts_with_all(["estimate", "bloom"]),
+        )
+        .arg(
+            Arg::new("skip_chars")
+            .short('s')
+            .long("skip-chars")
+            .value_name("N")
+            .help("Avoid comparing the first N characters")
+            .value_parser(value_parser!(usize))
+            .default_value("0"),
+        )
+        .arg(
+            Arg::new("check_chars")
+            .short('w')
+            .long("check-chars")
+            .value_name("N")
+            .help("Compare no more than N characters in lines")
+            .value_parser(value_parser!(usize)),
+        )
+        .arg(
+            Arg::new("ignore_case")
+            .short('i')
+            .long("ignore-case")
+            .help("Ignore differences in case when comparing")
+            .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("mask")
+            .long("mask")
+            .help("Replace numbers, hex values and UUIDs with placeholders before comparing")
+            .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("normalize")
+            .long("normalize")
+            .value_name("FORM")
+            .help("Compare lines in Unicode normalization FORM")
+            .value_parser(["nfc", "nfkc"]),
+        )
+        .arg(
+            Arg::new("ignore_trailing_space")
+            .long("ignore-trailing-space")
+            .help("Ignore whitespace at the end of lines when comparing")
+            .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("squeeze_space")
+            .long("squeeze-space")
+            .help("Compare runs of whitespace as a single space")
+            .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("ignore_blank_lines")
+            .long("ignore-blank-lines")
+            .help("Drop empty and whitespace-only lines")
+            .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("fuzzy")
+            .long("fuzzy")
+            .help("Treat adjacent lines as duplicates when they are --similarity alike")
+            .action(ArgAction::SetTrue)
+            .conflicts_with_all(["global", "estimate", "bloom"]),
+        )
+        .arg(
+            Arg::new("similarity")
+            .long("similarity")
+            .value_name("THRESHOLD")
+            .help("Minimum similarity for --fuzzy, from 0 to 1")
+            .default_value("0.9")
+            .requires("fuzzy"),
+        )
+        .arg(
+            Arg::new("fuzzy_metric")
+            .long("fuzzy-metric")
+            .value_name("METRIC")
+            .help("How --fuzzy measures similarity")
+            .value_parser(["levenshtein", "jaccard"])
+            .default_value("levenshtein")
+            .requires("fuzzy"),
+        )
+        .arg(
+            Arg::new("global")
+            .long("global")
+            .help("Remove duplicates anywhere in the input, keeping the first occurrence")
+            .action(ArgAction::SetTrue)
+            .conflicts_with_all(["all_repeated", "group"]),
+        )
+        .arg(
+            Arg::new("digest")
+            .long("digest")
+            .help("Remember 128-bit digests of keys instead of the keys themselves")
+            .action(ArgAction::SetTrue)
+            .requires("global"),
+        )
+        .arg(
+            Arg::new("memory_limit")
+            .long("memory-limit")
+            .value_name("SIZE")
+            .help("Spill keys to temporary files once they use about SIZE bytes (K, M, G suffixes)")
+            .requires("global"),
+        )
+        .arg(
+            Arg::new("temp_dir")
+            .short('T')
+            .long("temporary-directory")
+            .value_name("DIR")
+            .help("Put spilled keys in DIR instead of the system temporary directory")
+            .requires("memory_limit"),
+        )
+        .arg(
+            Arg::new("estimate")
+            .long("estimate")
+            .help("Only print an approximate count of distinct lines (HyperLogLog)")
+            .action(ArgAction::SetTrue)
+            .conflicts_with_all(["count", "repeated", "unique", "all_repeated", "group", "global"]),
+        )
+        .arg(
+            Arg::new("precision")
+            .long("precision")
+            .value_name("P")
+            .help("Use 2^P HyperLogLog registers (4-18)")
+            .value_parser(value_parser!(u8).range(4..=18))
+            .default_value("14")
+            .requires("estimate"),
+        )
+        .arg(
+            Arg::new("bloom")
+            .long("bloom")
+            .help("Remove duplicates anywhere using a Bloom filter, which may drop a few unique lines")
+            .action(ArgAction::SetTrue)
+            .conflicts_with_all(["count", "repeated", "unique", "all_repeated", "group", "global", "estimate"]),
+        )
+        .arg(
+            Arg::new("fp_rate")
+            .long("fp-rate")
+            .value_name("RATE")
+            .help("Target false-positive rate of the Bloom filter")
+            .default_value("0.01")
+            .requires("bloom"),
+        )
+        .arg(
+            Arg::new("expected_items")
+            .long("expected-items")
+            .value_name("N")
+            .help("Number of distinct lines the Bloom filter is sized for")
+            .value_parser(value_parser!(usize))
+            .default_value("1000000")
+            .requires("bloom"),
+        )
+        .arg(
+            Arg::new("histogram")
+            .long("histogram")
+            .help("Count every distinct key and print them by frequency, with percentages")
+            .action(ArgAction::SetTrue)
+            .conflicts_with_all([
+                "all_repeated", "group", "global", "estimate", "bloom", "keep_last", "fuzzy",
+            ]),
+        )
+        .arg(
+            Arg::new("top")
+            .long("top")
+            .value_name("N")
+            .help("With --histogram, only print the N most frequent keys")
+            .value_parser(value_parser!(u64).range(1..))
+            .requires("histogram"),
+        )
+        .arg(
+            Arg::new("format")
+            .long("format")
+            .value_name("FORMAT")
+            .help("Output format of --histogram")
+            .default_value("text")
+            .value_parser(["text", "csv", "json"])
+            .requires("histogram"),
+        )
+        .arg(
+            Arg::new("syslog")
+            .long("syslog")
+            .help("Print lines as they arrive and summarize repeats like syslog")
+            .action(ArgAction::SetTrue)
+            .conflicts_with_all([
+                "count", "repeated", "unique", "all_repeated", "group", "global", "estimate", "bloom", "histogram",
+                "keep_last",
+            ]),
+        )
+        .arg(
+            Arg::new("summary_format")
+            .long("summary-format")
+            .value_name("FORMAT")
+            .help("Summary line for --syslog, {count} is replaced by the number of repeats")
+            .default_value(DEFAULT_SUMMARY_FORMAT)
+            .requires("syslog"),
+        )
+        .arg(
+            Arg::new("window")
+            .long("window")
+            .value_name("DURATION")
+            .help(concat!(
+                "With --syslog, print the summary after DURATION without input (e.g. 500ms, 30s, 5m); ",
+                "with --time-field, drop lines whose key was seen less than DURATION before",
+            )),
+        )
+        .arg(
+            Arg::new("time_field")
+            .long("time-field")
+            .value_name("N")
+            .help("Take the event time for --window from blank-separated field N on, leaving it out of the -f key")
+            .value_parser(value_parser!(u64).range(1..))
+            .requires("window")
+            .conflicts_with_all([
+                "repeated", "unique", "all_repeated", "group", "global", "estimate", "bloom", "histogram", "syslog",
+                "keep_last",
+            ]),
+        )
+        .arg(
+            Arg::new("time_format")
+            .long("time-format")
+            .value_name("FORMAT")
+            .help("Format of --time-field: rfc3339, rfc2822, epoch, epoch-ms or a strftime pattern")
+            .default_value("rfc3339")
+            .requires("time_field"),
+        )
         .get_matches();
 
+    let all_repeated = matches.get_one::<String>("all_repeated").map(|s| match s.as_str() {
+        "prepend" => Delimit::Prepend,
+        "separate" => Delimit::Separate,
+        _ => Delimit::None,
+    });
+    let group = matches.get_one::<String>("group").map(|s| match s.as_str() {
+        "prepend" => Group::Prepend,
+        "append" => Group::Append,
+        "both" => Group::Both,
+        _ => Group::Separate,
+    });
+
+    let memory_limit = match matches.get_one::<String>("memory_limit") {
+        Some(s) => Some(parse_size(s).map_err(|e| format!("invalid memory limit -- {}", e))?),
+        None => None,
+    };
+
+    let bloom = if matches.get_flag("bloom") {
+        let rate = matches.get_one::<String>("fp_rate").unwrap();
+        let fp_rate = match rate.parse::<f64>() {
+            Ok(v) if v > 0.0 && v < 1.0 => v,
+            _ => return Err(format!("invalid false-positive rate -- {}", rate).into()),
+        };
+        Some(BloomSpec {
+            fp_rate,
+            expected_items: *matches.get_one::<usize>("expected_items").unwrap(),
+        })
+    } else {
+        None
+    };
+
+    let inputs = match matches.get_one::<String>("files0_from") {
+        Some(source) => {
+            let mut list = Vec::new();
+            open(source)
+                .and_then(|mut file| Ok(file.read_to_end(&mut list)?))
+                .map_err(|e| format!("{}: {}", source, e))?;
+            let names = inputs::parse_files0(source, &list)?;
+            if names.is_empty() {
+                return Err(format!("{}: no input files", source).into());
+            }
+            names
+        }
+        None => matches
+            .get_many::<String>("inputs")
+            .map(|names| names.cloned().collect())
+            .unwrap_or_default(),
+    };
+
+    let histogram = matches.get_flag("histogram").then(|| Histogram {
+        format: match matches.get_one::<String>("format").unwrap().as_str() {
+            "csv" => Format::Csv,
+            "json" => Format::Json,
+            _ => Format::Text,
+        },
+        top: matches.get_one::<u64>("top").map(|n| *n as usize),
+    });
+
+    let strategy = if let Some(index) = matches.get_one::<u64>("key") {
+        KeyStrategy::Column {
+            index: *index as usize,
+            delimiter: matches.get_one::<String>("delimiter").unwrap().to_string(),
+        }
+    } else if let Some(pattern) = matches.get_one::<String>("key_regex") {
+        KeyStrategy::Regex(Regex::new(pattern).map_err(|_| format!("invalid key regex -- {}", pattern))?)
+    } else if let Some(paths) = matches.get_many::<String>("json_key") {
+        KeyStrategy::Json(paths.map(|path| JsonPath::parse(path)).collect::<Result<_, _>>()?)
+    } else if matches.get_flag("json_canonical") {
+        KeyStrategy::Json(vec![JsonPath::parse(".")?])
+    } else {
+        KeyStrategy::Fields
+    };
+
+    let fuzzy = if matches.get_flag("fuzzy") {
+        let similarity = matches.get_one::<String>("similarity").unwrap();
+        let threshold = match similarity.parse::<f64>() {
+            Ok(v) if (0.0..=1.0).contains(&v) => v,
+            _ => return Err(format!("invalid similarity -- {}", similarity).into()),
+        };
+        let metric = match matches.get_one::<String>("fuzzy_metric").unwrap().as_str() {
+            "jaccard" => Metric::Jaccard,
+            _ => Metric::Levenshtein,
+        };
+        Some(Fuzzy { metric, threshold })
+    } else {
+        None
+    };
+
+    let window = match matches.get_one::<String>("window") {
+        Some(s) => Some(parse_duration(s).map_err(|e| format!("invalid window -- {}", e))?),
+        None => None,
+    };
+    let time_field = matches.get_one::<u64>("time_field").map(|n| *n as usize);
+    if window.is_some() && time_field.is_none() && !matches.get_flag("syslog") {
+        return Err("--window requires --syslog or --time-field".into());
+    }
+
     Ok(Config {
         in_file: matches.get_one::<String>("in_file").unwrap().to_string(),
-        out_file: matches.get_one::<String>("out_file").map(|s| s.to_string()),
+        inputs,
+        per_file: matches.get_flag("per_file"),
+        merge: matches.get_flag("merge"),
+        ignore_blank_lines: matches.get_flag("ignore_blank_lines"),
+        on_invalid: match matches.get_one::<String>("on_invalid").unwrap().as_str() {
+            "skip" => OnInvalid::Skip,
+            "passthrough" => OnInvalid::Passthrough,
+            _ => OnInvalid::Error,
+        },
+        with_filename: matches.get_flag("with_filename"),
+        out_file: matches.get_one::<String>("output").or(matches.get_one("out_file")).map(|s| s.to_string()),
+        append: matches.get_flag("append"),
         count: matches.get_flag("count"),
+        repeated: matches.get_flag("repeated"),
+        unique: matches.get_flag("unique"),
+        all_repeated,
+        group,
+        key: KeySpec {
+            strategy,
+            skip_fields: *matches.get_one::<usize>("skip_fields").unwrap(),
+            skip_chars: *matches.get_one::<usize>("skip_chars").unwrap(),
+            check_chars: matches.get_one::<usize>("check_chars").copied(),
+            ignore_case: matches.get_flag("ignore_case"),
+            mask: matches.get_flag("mask"),
+            normalize: matches.get_one::<String>("normalize").map(|s| match s.as_str() {
+                "nfkc" => Normalization::Nfkc,
+                _ => Normalization::Nfc,
+            }),
+            ignore_trailing_space: matches.get_flag("ignore_trailing_space"),
+            squeeze_space: matches.get_flag("squeeze_space"),
+        },
+        global: matches.get_flag("global"),
+        digest: matches.get_flag("digest"),
+        memory_limit,
+        temp_dir: matches.get_one::<String>("temp_dir").map(|s| s.to_string()),
+        estimate: matches
+            .get_flag("estimate")
+            .then(|| *matches.get_one::<u8>("precision").unwrap()),
+        bloom,
+        histogram,
+        keep_last: matches.get_flag("keep_last"),
+        syslog: matches.get_flag("syslog"),
+        summary_format: matches.get_one::<String>("summary_format").unwrap().to_string(),
+        window,
+        time_field,
+        time_format: TimeFormat::parse(matches.get_one::<String>("time_format").unwrap()),
+        fuzzy,
+        terminator: if matches.get_flag("zero_terminated") { b'\0' } else { b'\n' },
     })
 }
 
+fn open(filename: &str) -> MyResult<Box<dyn BufRead>> {
+    match filename {
+        "-" => Ok(Box::new(BufReader::new(io::stdin()))),
+        _ => Ok(Box::new(BufReader::new(File::open(filename)?))),
+    }
+}
+
+/// Opens `OUT_FILE` for `run`. Replacing an input file is safe as the new
+/// contents only take its place at the end, but appending to one would read
+/// our own output back, so that is refused.
+fn create(config: &Config, filename: &str) -> MyResult<OutFile> {
+    if config.append {
+        if config.files().iter().any(|input| out_file::same_file(input, filename)) {
+            return Err("input file is output file".into());
+        }
+        Ok(OutFile::append(filename)?)
+    } else {
+        Ok(OutFile::replace(filename)?)
+    }
+}
+
 pub fn run(config: Config) -> MyResult<()> {
-    println!("{:?}", config);
+    match config.out_file.as_deref() {
+        None | Some("-") => uniq_files(BufWriter::new(io::stdout()), &config),
+        Some(filename) => {
+            let out = create(&config, filename).map_err(|e| format!("{}: {}", filename, e))?;
+            let mut writer = BufWriter::new(out);
+            uniq_files(&mut writer, &config)?;
+            writer.into_inner().map_err(|e| e.into_error())?.commit()?;
+            Ok(())
+        }
+    }
+}
+
+/// Runs `uniq` over the input files as one stream, or with `--per-file`
+/// once for every file. With `--merge` the stream is the inputs merged in
+/// key order.
+fn uniq_files(mut output: impl Write, config: &Config) -> MyResult<()> {
+    let files = config.files();
+    if config.merge {
+        return uniq_opened(|config| Ok(Box::new(Merge::new(config)?)), output, config);
+    }
+    if files.len() == 1 && !config.with_filename {
+        return uniq_opened(
+            |config| {
+                let name = &config.files()[0];
+                Ok(open(name).map_err(|e| format!("{}: {}", name, e))?)
+            },
+            output,
+            config,
+        );
+    }
+    if config.per_file {
+        for i in 0..files.len() {
+            uniq_opened(
+                move |config| Ok(Box::new(Inputs::new(config.files(), i, i + 1, config.terminator, config.with_filename)?)),
+                &mut output,
+                config,
+            )?;
+        }
+        Ok(())
+    } else {
+        uniq_opened(
+            |config| {
+                let files = config.files();
+                Ok(Box::new(Inputs::new(files, 0, files.len(), config.terminator, config.with_filename)?))
+            },
+            output,
+            config,
+        )
+    }
+}
+
+/// Runs `uniq` over the input `open_input` makes of `config`, or with
+/// `--syslog --window` runs `uniq_syslog_window`, which makes it itself.
+fn uniq_opened<F>(open_input: F, output: impl Write, config: &Config) -> MyResult<()>
+where
+    F: for<'c> FnOnce(&'c Config) -> MyResult<Box<dyn BufRead + 'c>> + Send + 'static,
+{
+    match config.window {
+        Some(window) if config.syslog && config.time_field.is_none() => {
+            uniq_syslog_window(open_input, output, config, window)
+        }
+        _ => uniq(open_input(config)?, output, config),
+    }
+}
+
+/// Collapses adjacent identical records read from `input` and writes the
+/// selected runs to `output`. Records are lines, or with `-z` NUL-terminated
+/// strings, and are handled as raw bytes so invalid UTF-8 is no error. By
+/// default one copy of every run is written, prefixed with the run length
+/// when `config.count` is set; `-d`, `-u`, `-D` and `--group` narrow or widen
+/// that selection. Terminators are not part of the comparison, and records
+/// are written with their original bytes.
+pub fn uniq(input: impl BufRead, output: impl Write, config: &Config) -> MyResult<()> {
+    uniq_records(filtered(input, config), output, config)
+}
+
+/// `input` without the blank and invalid records `uniq` leaves out.
+fn filtered<'a>(input: impl BufRead + 'a, config: &'a Config) -> Box<dyn BufRead + 'a> {
+    let check_json = matches!(config.key.strategy, KeyStrategy::Json(_)) && config.on_invalid != OnInvalid::Passthrough;
+    if !config.ignore_blank_lines && !check_json {
+        return Box::new(input);
+    }
+
+    let mut line: u64 = 0;
+    let keep = move |record: &[u8]| {
+        line += 1;
+        if config.ignore_blank_lines && record.iter().all(u8::is_ascii_whitespace) {
+            return Ok(false);
+        }
+        if check_json && !json::is_valid(record) {
+            return match config.on_invalid {
+                OnInvalid::Error => Err(io::Error::other(format!("line {}: invalid JSON", line))),
+                _ => Ok(false),
+            };
+        }
+        Ok(true)
+    };
+    Box::new(Filter::new(input, config.terminator, config.with_filename, keep))
+}
+
+/// `uniq` once blank and invalid records are out of the way.
+fn uniq_records(mut input: impl BufRead, output: impl Write, config: &Config) -> MyResult<()> {
+    if let (Some(field), Some(window)) = (config.time_field, config.window) {
+        return uniq_window(input, output, config, field, window);
+    }
+    if config.syslog {
+        return uniq_syslog(input, output, config);
+    }
+    if config.global {
+        return uniq_global(input, output, config);
+    }
+    if let Some(precision) = config.estimate {
+        return uniq_estimate(input, output, config, precision);
+    }
+    if let Some(spec) = config.bloom {
+        return uniq_bloom(input, output, config, spec);
+    }
+    if let Some(histogram) = config.histogram {
+        return uniq_histogram(input, output, config, histogram);
+    }
+
+    let mut output = Output::new(output, config);
+    let keep_all = config.all_repeated.is_some() || config.group.is_some();
+    let mut record = Vec::new();
+    let mut run = Run { key: Vec::new(), records: Vec::new(), count: 0 };
+    let mut groups: usize = 0;
+
+    loop {
+        let bytes_read = input.read_until(config.terminator, &mut record)?;
+        if bytes_read == 0 {
+            break; // reached EOF
+        }
+
+        let key = config.key_of(&record);
+        if run.count > 0 && config.same_key(&key, &run.key) {
+            run.count += 1;
+            if keep_all {
+                run.records.push(std::mem::take(&mut record));
+            } else if config.keep_last {
+                run.records[0] = std::mem::take(&mut record);
+            }
+        } else {
+            run.key = key.into_owned();
+            write_run(&mut output, &run, config, &mut groups)?;
+            run.records.clear();
+            run.records.push(std::mem::take(&mut record));
+            run.count = 1;
+        }
+
+        record.clear(); // clear for next record
+    }
+    write_run(&mut output, &run, config, &mut groups)?;
+    if config.group == Some(Group::Both) && groups > 0 {
+        output.separator()?;
+    }
+    output.flush()?;
+    Ok(())
+}
+
+/// Removes duplicate records anywhere in `input`, keeping the first
+/// occurrence of every key in its original position (or with `--keep-last`
+/// the last occurrence in its position). Records are written as soon as
+/// they are first seen unless `-c`, `-d`, `-u` or `--keep-last` need the
+/// whole input, in which case output waits for EOF. With
+/// `config.memory_limit` set, the key set moves to disk once it outgrows
+/// the limit (see the `external` module).
+pub fn uniq_global(mut input: impl BufRead, output: impl Write, config: &Config) -> MyResult<()> {
+    let mut output = Output::new(output, config);
+    let buffered = config.count || config.repeated || config.unique || config.keep_last;
+    let mut counter = Counter::default();
+    let mut record = Vec::new();
+    let mut seq: u64 = 0;
+
+    loop {
+        let bytes_read = input.read_until(config.terminator, &mut record)?;
+        if bytes_read == 0 {
+            break; // reached EOF
+        }
+
+        let key = StoredKey::new(config.key_of(&record), config.digest);
+        if counter.add(key, seq, &mut record, buffered, config.keep_last) && !buffered {
+            output.record(None, &record)?;
+        }
+
+        record.clear(); // clear for next record
+        seq += 1;
+
+        if let Some(limit) = config.memory_limit
+            && counter.memory > limit
+        {
+            return external::finish(counter.seen, counter.entries, seq, input, &mut output, config, limit);
+        }
+    }
+
+    if buffered {
+        let mut entries = counter.entries;
+        if config.keep_last {
+            entries.sort_unstable_by_key(|entry| entry.seq);
+        }
+        for entry in &entries {
+            if config.selects(entry.count) {
+                output.record(config.count.then_some(entry.count), &entry.record)?;
+            }
+        }
+    }
+    output.flush()?;
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Counts records per key in first-seen order, as used by `--global` and
+/// `--histogram`.
+#[derive(Default)]
+struct Counter {
+    seen: HashMap<StoredKey, usize>,
+    entries: Vec<Occurrence>,
+    /// rough number of bytes held, see `external::ENTRY_OVERHEAD`
+    memory: usize,
+}
+
+impl Counter {
+    /// Counts an occurrence of `key` at position `seq` and returns whether
+    /// the key is new. With `keep` the record of a new key is moved into the
+    /// counter, and with `keep_last` so is that of every later occurrence.
+    fn add(&mut self, key: StoredKey, seq: u64, record: &mut Vec<u8>, keep: bool, keep_last: bool) -> bool {
+        match self.seen.entry(key) {
+            Entry::Occupied(e) => {
+                let entry = &mut self.entries[*e.get()];
+                entry.count += 1;
+                if keep_last {
+                    self.memory = self.memory + record.len() - entry.record.len();
+                    entry.seq = seq;
+                    entry.record = std::mem::take(record);
+                }
+                false
+            }
+            Entry::Vacant(e) => {
+                self.memory += e.key().len() + record.len() + external::ENTRY_OVERHEAD;
+                e.insert(self.entries.len());
+                let record = if keep { std::mem::take(record) } else { Vec::new() };
+                self.entries.push(Occurrence { seq, record, count: 1 });
+                true
+            }
+        }
+    }
+}
+
+/// The record kept for a key in global mode (empty once it has been
+/// written), its position in the input and how often the key occurred.
+struct Occurrence {
+    seq: u64,
+    record: Vec<u8>,
+    count: usize,
+}
+
+/// Streams `input` the way syslog collapses repeated messages: the first
+/// record of a run is written immediately, and once the run ends the number
+/// of repeats is written using `config.summary_format`. Output is flushed
+/// after every write so the mode can sit at the end of a `tail -f`
+/// pipeline. Reporting repeats after a quiet `config.window` as well takes
+/// `uniq_syslog_window`.
+pub fn uniq_syslog(mut input: impl BufRead, output: impl Write, config: &Config) -> MyResult<()> {
+    let mut output = Output::new(output, config);
+    let mut state = Syslog { key: None, repeats: 0 };
+    let mut record = Vec::new();
+    loop {
+        let bytes_read = input.read_until(config.terminator, &mut record)?;
+        if bytes_read == 0 {
+            break; // reached EOF
+        }
+        state.push(&record, &mut output, config)?;
+        record.clear(); // clear for next record
+    }
+    state.summarize(&mut output, config)
+}
+
+/// `uniq_syslog` that also writes the repeats once the input has been quiet
+/// for `window`. The records are read on a thread of their own, which is
+/// not waited for: when the output fails it may be stuck in a read that
+/// only ends with the input. So it makes its input itself with
+/// `open_input`, from its own copy of `config`.
+pub fn uniq_syslog_window<F>(open_input: F, output: impl Write, config: &Config, window: Duration) -> MyResult<()>
+where
+    F: for<'c> FnOnce(&'c Config) -> MyResult<Box<dyn BufRead + 'c>> + Send + 'static,
+{
+    let mut output = Output::new(output, config);
+    let mut state = Syslog { key: None, repeats: 0 };
+
+    let (tx, rx) = mpsc::channel();
+    let own = config.clone();
+    thread::spawn(move || {
+        let mut input = match open_input(&own) {
+            Ok(input) => filtered(input, &own),
+            Err(e) => {
+                let _ = tx.send(Err(io::Error::other(e.to_string())));
+                return;
+            }
+        };
+        loop {
+            let mut record = Vec::new();
+            let result = input.read_until(own.terminator, &mut record).map(|n| (n, record));
+            let done = !matches!(result, Ok((n, _)) if n > 0);
+            if tx.send(result).is_err() || done {
+                break;
+            }
+        }
+    });
+    loop {
+        match rx.recv_timeout(window) {
+            Ok(Ok((0, _))) | Err(RecvTimeoutError::Disconnected) => break,
+            Ok(Ok((_, record))) => state.push(&record, &mut output, config)?,
+            Ok(Err(e)) => return Err(e.into()),
+            Err(RecvTimeoutError::Timeout) => state.summarize(&mut output, config)?,
+        }
+    }
+
+    state.summarize(&mut output, config)
+}
+
+/// The key of the current `--syslog` run and the repeats not yet reported.
+struct Syslog {
+    key: Option<Vec<u8>>,
+    repeats: usize,
+}
+
+impl Syslog {
+    fn push(&mut self, record: &[u8], output: &mut Output<impl Write>, config: &Config) -> MyResult<()> {
+        let key = config.key_of(record);
+        if self.key.as_deref().is_some_and(|current| config.same_key(&key, current)) {
+            self.repeats += 1;
+            return Ok(());
+        }
+        self.summarize(output, config)?;
+        self.key = Some(key.into_owned());
+        output.record(None, record)?;
+        output.flush()?;
+        Ok(())
+    }
+
+    fn summarize(&mut self, output: &mut Output<impl Write>, config: &Config) -> MyResult<()> {
+        if self.repeats > 0 {
+            let summary = config.summary_format.replace("{count}", &self.repeats.to_string());
+            output.text(&summary)?;
+            output.flush()?;
+            self.repeats = 0;
+        }
+        Ok(())
+    }
+}
+
+/// Writes a record unless one with the same key was seen less than `window`
+/// before it, going by the event times from field `field` on rather than the
+/// clock, so replaying old logs gives the same result. A key of fields is
+/// taken with the time left out. Every record of a key
+/// extends its window. With `-c` written records are held until their window
+/// closes and are then prefixed with the number of records they stand for,
+/// the suppressed ones included. Keys are forgotten once their window is
+/// over, so memory follows the number of keys active within one window.
+pub fn uniq_window(
+    mut input: impl BufRead,
+    output: impl Write,
+    config: &Config,
+    field: usize,
+    window: Duration,
+) -> MyResult<()> {
+    let window = TimeDelta::from_std(window).map_err(|_| "invalid window -- too long")?;
+    let mut output = Output::new(output, config);
+    let mut seen: HashMap<Vec<u8>, Seen> = HashMap::new();
+    // records held for -c, oldest first; the first has id `written`
+    let mut pending: VecDeque<Pending> = VecDeque::new();
+    let mut written: u64 = 0;
+    let mut latest: Option<DateTime<Utc>> = None;
+    let mut next_sweep = SWEEP_MIN;
+    let mut record = Vec::new();
+    let mut line: u64 = 0;
+
+    loop {
+        let bytes_read = input.read_until(config.terminator, &mut record)?;
+        if bytes_read == 0 {
+            break; // reached EOF
+        }
+        line += 1;
+
+        let payload = config.payload(&record);
+        let from = from_field(payload, field - 1);
+        let (time, len) = config
+            .time_format
+            .parse_start(&String::from_utf8_lossy(from))
+            .ok_or_else(|| {
+                let text = String::from_utf8_lossy(nth_field(payload, field - 1));
+                format!("line {}: invalid timestamp -- {}", line, text)
+            })?;
+        let now = *latest.insert(latest.map_or(time, |latest| latest.max(time)));
+
+        // the time differs from record to record, so it is no part of a
+        // key made of fields
+        let key = match config.key.strategy {
+            KeyStrategy::Fields => {
+                let start = payload.len() - from.len();
+                config.key.key(&[&payload[..start], &from[len..]].concat()).into_owned()
+            }
+            _ => config.key_of(&record).into_owned(),
+        };
+        match seen.get_mut(&key) {
+            Some(entry) if time - entry.last < window => {
+                entry.last = entry.last.max(time);
+                if let Some(id) = entry.pending.filter(|id| *id >= written) {
+                    pending[(id - written) as usize].count += 1;
+                }
+            }
+            _ if config.count => {
+                let id = written + pending.len() as u64;
+                seen.insert(key.clone(), Seen { last: time, pending: Some(id) });
+                pending.push_back(Pending { key, record: std::mem::take(&mut record), count: 1 });
+            }
+            _ => {
+                output.record(None, &record)?;
+                seen.insert(key, Seen { last: time, pending: None });
+            }
+        }
+        record.clear(); // clear for next record
+
+        while let Some(front) = pending.front()
+            && seen.get(&front.key).is_none_or(|entry| now - entry.last >= window)
+        {
+            let front = pending.pop_front().unwrap();
+            output.record(Some(front.count), &front.record)?;
+            written += 1;
+        }
+        if seen.len() >= next_sweep {
+            seen.retain(|_, entry| now - entry.last < window || entry.pending.is_some_and(|id| id >= written));
+            next_sweep = (2 * seen.len()).max(SWEEP_MIN);
+        }
+    }
+
+    for front in pending {
+        output.record(Some(front.count), &front.record)?;
+    }
+    output.flush()?;
+    Ok(())
+}
+
+/// Number of keys `uniq_window` keeps before it first drops expired ones.
+const SWEEP_MIN: usize = 1024;
+
+/// The latest event time of a key in `uniq_window`, and the id of its
+/// record held for `-c`.
+struct Seen {
+    last: DateTime<Utc>,
+    pending: Option<u64>,
+}
+
+/// A record `uniq_window` holds for `-c` until its window closes.
+struct Pending {
+    key: Vec<u8>,
+    record: Vec<u8>,
+    count: usize,
+}
+
+/// Writes the approximate number of distinct keys in `input` followed by
+/// the relative standard error of the estimate.
+pub fn uniq_estimate(mut input: impl BufRead, mut output: impl Write, config: &Config, precision: u8) -> MyResult<()> {
+    let mut hll = HyperLogLog::new(precision);
+    let mut record = Vec::new();
+
+    loop {
+        let bytes_read = input.read_until(config.terminator, &mut record)?;
+        if bytes_read == 0 {
+            break; // reached EOF
+        }
+        hll.insert(&config.key_of(&record));
+        record.clear(); // clear for next record
+    }
+
+    writeln!(output, "{:.0}\t±{:.2}%", hll.estimate(), hll.error_bound() * 100.0)?;
+    output.flush()?;
+    Ok(())
+}
+
+/// Like `uniq_global` without counts, but remembers keys in a fixed-size
+/// Bloom filter: memory never grows, and a false positive drops a record
+/// whose key was in fact new.
+pub fn uniq_bloom(mut input: impl BufRead, output: impl Write, config: &Config, spec: BloomSpec) -> MyResult<()> {
+    let mut output = Output::new(output, config);
+    let mut bloom = BloomFilter::new(spec.expected_items, spec.fp_rate);
+    let mut record = Vec::new();
+
+    loop {
+        let bytes_read = input.read_until(config.terminator, &mut record)?;
+        if bytes_read == 0 {
+            break; // reached EOF
+        }
+        if !bloom.insert(&config.key_of(&record)) {
+            output.record(None, &record)?;
+        }
+        record.clear(); // clear for next record
+    }
+    output.flush()?;
+    Ok(())
+}
+
+/// Counts every distinct key in `input` and writes one row per key, most
+/// frequent first and ties in order of first occurrence, with its share of
+/// all records and the running total of those shares. `-d` and `-u` select
+/// keys as they do for `--global`.
+pub fn uniq_histogram(
+    mut input: impl BufRead,
+    output: impl Write,
+    config: &Config,
+    histogram: Histogram,
+) -> MyResult<()> {
+    let mut counter = Counter::default();
+    let mut record = Vec::new();
+    let mut seq: u64 = 0;
+
+    loop {
+        let bytes_read = input.read_until(config.terminator, &mut record)?;
+        if bytes_read == 0 {
+            break; // reached EOF
+        }
+        let key = StoredKey::new(config.key_of(&record), config.digest);
+        counter.add(key, seq, &mut record, true, false);
+        record.clear(); // clear for next record
+        seq += 1;
+    }
+
+    let mut entries = counter.entries;
+    entries.retain(|entry| config.selects(entry.count));
+    // stable, so equal counts stay in first-seen order
+    entries.sort_by_key(|entry| Reverse(entry.count));
+    entries.truncate(histogram.top.unwrap_or(usize::MAX));
+
+    let total = seq.max(1) as f64;
+    let mut cumulative = 0;
+    let rows = entries.iter().map(|entry| {
+        cumulative += entry.count;
+        let (file, line) = match config.with_filename {
+            true => {
+                let (index, line) = inputs::untag(&entry.record);
+                (Some(config.files()[index].as_str()), line)
+            }
+            false => (None, entry.record.as_slice()),
+        };
+        let line = line.strip_suffix(&[config.terminator]).unwrap_or(line);
+        (entry.count, entry.count as f64 * 100.0 / total, cumulative as f64 * 100.0 / total, file, line)
+    });
+
+    let mut output = BufWriter::new(output);
+    match histogram.format {
+        Format::Text => {
+            for (count, percent, cumulative, file, line) in rows {
+                write!(output, "{:>7} {:>6.2}% {:>6.2}% ", count, percent, cumulative)?;
+                if let Some(file) = file {
+                    write!(output, "{}:", file)?;
+                }
+                output.write_all(line)?;
+                output.write_all(&[config.terminator])?;
+            }
+        }
+        Format::Csv => {
+            let file_column = if config.with_filename { "file," } else { "" };
+            writeln!(output, "count,percent,cumulative_percent,{}line", file_column)?;
+            for (count, percent, cumulative, file, line) in rows {
+                write!(output, "{},{:.2},{:.2},", count, percent, cumulative)?;
+                if let Some(file) = file {
+                    write!(output, "{},", csv_field(file.as_bytes()))?;
+                }
+                writeln!(output, "{}", csv_field(line))?;
+            }
+        }
+        Format::Json => {
+            let rows: Vec<_> = rows
+                .map(|(count, percent, cumulative, file, line)| {
+                    let mut row = serde_json::json!({
+                        "count": count,
+                        "percent": round2(percent),
+                        "cumulative_percent": round2(cumulative),
+                    });
+                    if let Some(file) = file {
+                        row["file"] = file.into();
+                    }
+                    row["line"] = String::from_utf8_lossy(line).into();
+                    row
+                })
+                .collect();
+            serde_json::to_writer_pretty(&mut output, &rows)?;
+            writeln!(output)?;
+        }
+    }
+    output.flush()?;
+    Ok(())
+}
+
+/// How global mode remembers a key: the key itself, or with `--digest` its
+/// 128-bit XXH3 hash, which keeps memory per key fixed at the (tiny) risk
+/// of two different keys colliding.
+#[derive(Debug, PartialEq, Eq, Hash)]
+enum StoredKey {
+    Full(Vec<u8>),
+    Digest(u128),
+}
+
+impl StoredKey {
+    fn new(key: Cow<[u8]>, digest: bool) -> Self {
+        if digest {
+            StoredKey::Digest(xxhash_rust::xxh3::xxh3_128(&key))
+        } else {
+            StoredKey::Full(key.into_owned())
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            StoredKey::Full(key) => key.len(),
+            StoredKey::Digest(_) => 16,
+        }
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            StoredKey::Full(key) => key.clone(),
+            StoredKey::Digest(digest) => digest.to_le_bytes().to_vec(),
+        }
+    }
+}
+
+/// A run of adjacent records with equal keys. Only the first (or with
+/// `--keep-last` the latest) record is kept unless every member has to be
+/// printed (`-D`, `--group`).
+struct Run {
+    key: Vec<u8>,
+    records: Vec<Vec<u8>>,
+    count: usize,
+}
+
+/// Writes records to the output. A record without its terminator can only
+/// be the last one of the input; it is written as-is, and only gets a
+/// terminator if something else has to be written after it.
+struct Output<W: Write> {
+    inner: W,
+    terminator: u8,
+    unterminated: bool,
+    /// the input files, if records are tagged for `--with-filename`
+    names: Option<Vec<String>>,
+}
+
+impl<W: Write> Output<W> {
+    fn new(inner: W, config: &Config) -> Self {
+        let names = config.with_filename.then(|| config.files().to_vec());
+        Output { inner, terminator: config.terminator, unterminated: false, names }
+    }
+
+    /// Writes `record`, with `-c` style `%7d ` prefix if `count` is given,
+    /// after the `name:` of its file with `--with-filename`.
+    fn record(&mut self, count: Option<usize>, record: &[u8]) -> io::Result<()> {
+        self.terminate()?;
+        let record = match &self.names {
+            Some(names) => {
+                let (index, record) = inputs::untag(record);
+                write!(self.inner, "{}:", names[index])?;
+                record
+            }
+            None => record,
+        };
+        if let Some(count) = count {
+            write!(self.inner, "{:>7} ", count)?;
+        }
+        self.inner.write_all(record)?;
+        self.unterminated = record.last() != Some(&self.terminator);
+        Ok(())
+    }
+
+    /// Writes an empty record, as used between groups.
+    fn separator(&mut self) -> io::Result<()> {
+        self.terminate()?;
+        self.inner.write_all(&[self.terminator])
+    }
+
+    /// Writes a line of text of our own, such as a `--syslog` summary.
+    fn text(&mut self, text: &str) -> io::Result<()> {
+        self.terminate()?;
+        self.inner.write_all(text.as_bytes())?;
+        self.inner.write_all(&[self.terminator])
+    }
+
+    fn terminate(&mut self) -> io::Result<()> {
+        if self.unterminated {
+            self.unterminated = false;
+            self.inner.write_all(&[self.terminator])?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl Config {
+    /// The input files: `inputs`, or else `in_file`.
+    pub fn files(&self) -> &[String] {
+        if self.inputs.is_empty() {
+            std::slice::from_ref(&self.in_file)
+        } else {
+            &self.inputs
+        }
+    }
+
+    /// `record` as read without its terminator, and with `--with-filename`
+    /// without the tag of its file.
+    fn payload<'a>(&self, record: &'a [u8]) -> &'a [u8] {
+        let record = if self.with_filename { inputs::untag(record).1 } else { record };
+        record.strip_suffix(&[self.terminator]).unwrap_or(record)
+    }
+
+    /// The comparison key of `record`, as it was read.
+    fn key_of<'a>(&self, record: &'a [u8]) -> Cow<'a, [u8]> {
+        self.key.key(self.payload(record))
+    }
+
+    /// Whether `key` continues the run started by `first`: equal keys, or
+    /// with `--fuzzy` keys similar enough. Comparing against the first key
+    /// rather than the previous one keeps a run from drifting.
+    fn same_key(&self, key: &[u8], first: &[u8]) -> bool {
+        match &self.fuzzy {
+            Some(fuzzy) if key != first => {
+                fuzzy.matches(&String::from_utf8_lossy(key), &String::from_utf8_lossy(first))
+            }
+            _ => key == first,
+        }
+    }
+
+    /// Whether a run of `count` records passes the `-d`/`-u`/`-D` selection.
+    fn selects(&self, count: usize) -> bool {
+        if count > 1 {
+            !self.unique
+        } else {
+            !(self.repeated || self.all_repeated.is_some())
+        }
+    }
+}
+
+impl KeySpec {
+    /// Returns the part of `record` (without its terminator) that is
+    /// compared: the `strategy` picks the bytes (by default skipping
+    /// `skip_fields` blank-separated fields), which are brought into the
+    /// `normalize` form, stripped of trailing whitespace and with whitespace
+    /// runs squeezed to one space if asked. Then `skip_chars` characters are
+    /// skipped, at most `check_chars` characters are kept, volatile numbers
+    /// and ids are replaced when `mask` is set, and the result is case
+    /// folded when `ignore_case` is set. Characters are UTF-8 sequences;
+    /// bytes that are not valid UTF-8 count as one character each and are
+    /// never normalized or folded.
+    pub fn key<'a>(&self, record: &'a [u8]) -> Cow<'a, [u8]> {
+        let mut key = match &self.strategy {
+            KeyStrategy::Fields => Cow::Borrowed(skip_fields(record, self.skip_fields)),
+            KeyStrategy::Column { index, delimiter } => {
+                Cow::Borrowed(nth_column(record, delimiter.as_bytes(), index - 1))
+            }
+            KeyStrategy::Regex(re) => Cow::Borrowed(match re.captures(record) {
+                Some(caps) => caps.get(1).or_else(|| caps.get(0)).map_or(&b""[..], |m| m.as_bytes()),
+                None => record,
+            }),
+            KeyStrategy::Json(paths) => json::key(record, paths).map_or(Cow::Borrowed(record), Cow::Owned),
+        };
+        if let Some(form) = self.normalize
+            && let Some(normalized) = normalize(&key, form)
+        {
+            key = Cow::Owned(normalized);
+        }
+        if self.ignore_trailing_space {
+            let end = key.iter().rposition(|b| !b.is_ascii_whitespace()).map_or(0, |i| i + 1);
+            key = sub_key(key, 0, end);
+        }
+        if self.squeeze_space
+            && let Some(squeezed) = squeeze_space(&key)
+        {
+            key = Cow::Owned(squeezed);
+        }
+
+        let start = char_offset(&key, self.skip_chars);
+        let end = match self.check_chars {
+            Some(n) => start + char_offset(&key[start..], n),
+            None => key.len(),
+        };
+        key = sub_key(key, start, end);
+        if self.mask
+            && let Cow::Owned(masked) = fuzzy::mask(&key)
+        {
+            key = Cow::Owned(masked);
+        }
+        if self.ignore_case {
+            Cow::Owned(case_fold(&key))
+        } else {
+            key
+        }
+    }
+}
+
+/// Parses a byte count with an optional K, M or G (binary) suffix.
+pub fn parse_size(val: &str) -> MyResult<usize> {
+    let (digits, unit) = match val.char_indices().last() {
+        Some((i, c)) if c.is_ascii_alphabetic() => (&val[..i], c.to_ascii_uppercase()),
+        _ => (val, 'B'),
+    };
+    let shift = match unit {
+        'B' => 0,
+        'K' => 10,
+        'M' => 20,
+        'G' => 30,
+        _ => return Err(From::from(val)),
+    };
+    match digits.parse::<usize>() {
+        Ok(v) if v > 0 => v.checked_mul(1 << shift).ok_or_else(|| From::from(val)),
+        _ => Err(From::from(val)),
+    }
+}
+
+/// Parses a duration such as `250ms`, `30s`, `5m` or `1h`; a bare number
+/// is in seconds.
+pub fn parse_duration(val: &str) -> MyResult<Duration> {
+    let split = val.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(val.len());
+    let (number, unit) = val.split_at(split);
+    let seconds = match unit {
+        "ms" => 0.001,
+        "" | "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        _ => return Err(From::from(val)),
+    };
+    match number.parse::<f64>() {
+        Ok(v) if v > 0.0 && v.is_finite() => Ok(Duration::from_secs_f64(v * seconds)),
+        _ => Err(From::from(val)),
+    }
+}
+
+// -------------------- helper functions --------------------
+fn is_blank(b: &u8) -> bool {
+    *b == b' ' || *b == b'\t'
+}
+
+/// Skips `n` fields, each being optional blanks followed by non-blanks.
+fn skip_fields(record: &[u8], n: usize) -> &[u8] {
+    let mut rest = record;
+    for _ in 0..n {
+        let start = rest.iter().position(|b| !is_blank(b)).unwrap_or(rest.len());
+        rest = &rest[start..];
+        let end = rest.iter().position(is_blank).unwrap_or(rest.len());
+        rest = &rest[end..];
+    }
+    rest
+}
+
+/// `record` from its `n`th (0-based) blank-separated field on, or nothing.
+fn from_field(record: &[u8], n: usize) -> &[u8] {
+    let rest = skip_fields(record, n);
+    let start = rest.iter().position(|b| !is_blank(b)).unwrap_or(rest.len());
+    &rest[start..]
+}
+
+/// The `n`th (0-based) blank-separated field of `record`, or nothing.
+fn nth_field(record: &[u8], n: usize) -> &[u8] {
+    let rest = from_field(record, n);
+    &rest[..rest.iter().position(is_blank).unwrap_or(rest.len())]
+}
+
+/// The `n`th (0-based) column of `record` split on `delimiter`, or nothing.
+fn nth_column<'a>(record: &'a [u8], delimiter: &[u8], n: usize) -> &'a [u8] {
+    let mut rest = record;
+    for _ in 0..n {
+        match find(rest, delimiter) {
+            Some(i) => rest = &rest[i + delimiter.len()..],
+            None => return b"",
+        }
+    }
+    match find(rest, delimiter) {
+        Some(i) => &rest[..i],
+        None => rest,
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if needle.is_empty() {
+        return None;
+    }
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+/// Byte offset of the `n`th character, or the length if there are fewer.
+/// Every byte that is not a UTF-8 continuation byte starts a character.
+fn char_offset(bytes: &[u8], n: usize) -> usize {
+    bytes
+        .iter()
+        .enumerate()
+        .filter(|(_, b)| (**b & 0xC0) != 0x80)
+        .nth(n)
+        .map_or(bytes.len(), |(i, _)| i)
+}
+
+/// `key[start..end]`, without copying a borrowed key.
+fn sub_key(key: Cow<'_, [u8]>, start: usize, end: usize) -> Cow<'_, [u8]> {
+    match key {
+        Cow::Borrowed(key) => Cow::Borrowed(&key[start..end]),
+        Cow::Owned(mut key) => {
+            key.truncate(end);
+            key.drain(..start);
+            Cow::Owned(key)
+        }
+    }
+}
+
+/// The valid UTF-8 in `bytes` in normalization `form`, or `None` if it is
+/// in that form already. Invalid bytes are kept as they are.
+fn normalize(bytes: &[u8], form: Normalization) -> Option<Vec<u8>> {
+    let is_normalized = |s: &str| match form {
+        Normalization::Nfc => unicode_normalization::is_nfc(s),
+        Normalization::Nfkc => unicode_normalization::is_nfkc(s),
+    };
+    if bytes.utf8_chunks().all(|chunk| is_normalized(chunk.valid())) {
+        return None;
+    }
+    let mut normalized = Vec::with_capacity(bytes.len());
+    for chunk in bytes.utf8_chunks() {
+        let valid = chunk.valid();
+        let valid: String = match form {
+            Normalization::Nfc => valid.nfc().collect(),
+            Normalization::Nfkc => valid.nfkc().collect(),
+        };
+        normalized.extend_from_slice(valid.as_bytes());
+        normalized.extend_from_slice(chunk.invalid());
+    }
+    Some(normalized)
+}
+
+/// `bytes` with every run of whitespace replaced by one space, or `None` if
+/// that changes nothing.
+fn squeeze_space(bytes: &[u8]) -> Option<Vec<u8>> {
+    let mut squeezed = Vec::with_capacity(bytes.len());
+    for &b in bytes {
+        if !b.is_ascii_whitespace() {
+            squeezed.push(b);
+        } else if squeezed.last() != Some(&b' ') {
+            squeezed.push(b' ');
+        }
+    }
+    (squeezed != bytes).then_some(squeezed)
+}
+
+/// Unicode case folding of the valid UTF-8 in `bytes`; invalid bytes are
+/// kept as they are.
+fn case_fold(bytes: &[u8]) -> Vec<u8> {
+    let mut folded = Vec::with_capacity(bytes.len());
+    for chunk in bytes.utf8_chunks() {
+        folded.extend_from_slice(caseless::default_case_fold_str(chunk.valid()).as_bytes());
+        folded.extend_from_slice(chunk.invalid());
+    }
+    folded
+}
+
+fn write_run(output: &mut Output<impl Write>, run: &Run, config: &Config, groups: &mut usize) -> MyResult<()> {
+    if run.count == 0 || !config.selects(run.count) {
+        return Ok(());
+    }
+
+    let separator_before = match (config.group, config.all_repeated) {
+        (Some(Group::Prepend | Group::Both), _) | (_, Some(Delimit::Prepend)) => true,
+        (Some(Group::Separate), _) | (_, Some(Delimit::Separate)) => *groups > 0,
+        _ => false,
+    };
+    if separator_before {
+        output.separator()?;
+    }
+
+    if config.group.is_some() || config.all_repeated.is_some() {
+        for record in &run.records {
+            output.record(None, record)?;
+        }
+    } else {
+        output.record(config.count.then_some(run.count), &run.records[0])?;
+    }
+
+    if config.group == Some(Group::Append) {
+        output.separator()?;
+    }
+    *groups += 1;
+    Ok(())
+}
+
+/// `field` as a CSV field, quoted when it holds a comma, quote or line break.
+fn csv_field(field: &[u8]) -> Cow<'_, str> {
+    let field = String::from_utf8_lossy(field);
+    if field.contains([',', '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+    } else {
+        field
+    }
+}
+
+fn round2(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0
+}
+
+// -------------------- tests --------------------
+#[cfg(test)]
+mod tests {
+    use super::fuzzy::{Fuzzy, Metric};
+    use super::{
+        parse_duration, parse_size, uniq, uniq_syslog_window, BloomSpec, Config, Delimit, Format, Group, Histogram, KeySpec, KeyStrategy,
+        MyResult, Normalization, OnInvalid,
+    };
+    use super::timestamp::TimeFormat;
+    use super::json::JsonPath;
+    use regex::bytes::Regex;
+    use std::io::{self, BufRead, BufReader, Cursor, Read};
+    use std::thread;
+    use std::time::Duration;
+
+    fn run_uniq(text: &str, config: &Config) -> MyResult<String> {
+        Ok(String::from_utf8(run_uniq_bytes(text.as_bytes(), config)?)?)
+    }
+
+    fn run_uniq_bytes(input: &[u8], config: &Config) -> MyResult<Vec<u8>> {
+        let mut output = Vec::new();
+        uniq(Cursor::new(input), &mut output, config)?;
+        Ok(output)
+    }
+
+    /// The key of `line` as text, with the newline stripped as `uniq` does.
+    fn key(spec: &KeySpec, line: &str) -> String {
+        let config = Config { key: spec.clone(), ..Config::default() };
+        String::from_utf8(config.key_of(line.as_bytes()).into_owned()).unwrap()
+    }
+
+    #[test]
+    fn test_uniq_empty() {
+        let res = run_uniq("", &Config::default());
+        assert_eq!(res.unwrap(), "");
+    }
+
+    #[test]
+    fn test_uniq_adjacent() {
+        let text = "a\na\nb\na\na\na\nc\n";
+        let res = run_uniq(text, &Config::default());
+        assert_eq!(res.unwrap(), "a\nb\na\nc\n");
+    }
+
+    #[test]
+    fn test_uniq_count() {
+        let config = Config { count: true, ..Config::default() };
+        let res = run_uniq("a\na\nb\n", &config);
+        assert_eq!(res.unwrap(), "      2 a\n      1 b\n");
+    }
+
+    #[test]
+    fn test_uniq_line_endings() {
+        // the missing newline on the last line does not break the run
+        let res = run_uniq("a\na", &Config::default());
+        assert_eq!(res.unwrap(), "a\n");
+
+        // a lone unterminated last line is written as-is
+        let res = run_uniq("a\nb", &Config::default());
+        assert_eq!(res.unwrap(), "a\nb");
+
+        // CRLF is preserved and "\r" takes part in the comparison
+        let res = run_uniq("a\r\na\r\na\n", &Config::default());
+        assert_eq!(res.unwrap(), "a\r\na\n");
+    }
+
+    #[test]
+    fn test_uniq_repeated() {
+        let text = "a\na\nb\nc\nc\nc\nd\n";
+        let config = Config { repeated: true, ..Config::default() };
+        assert_eq!(run_uniq(text, &config).unwrap(), "a\nc\n");
+
+        let config = Config { repeated: true, count: true, ..Config::default() };
+        assert_eq!(run_uniq(text, &config).unwrap(), "      2 a\n      3 c\n");
+    }
+
+    #[test]
+    fn test_uniq_unique() {
+        let text = "a\na\nb\nc\nc\nc\nd\n";
+        let config = Config { unique: true, ..Config::default() };
+        assert_eq!(run_uniq(text, &config).unwrap(), "b\nd\n");
+
+        let config = Config { unique: true, count: true, ..Config::default() };
+        assert_eq!(run_uniq(text, &config).unwrap(), "      1 b\n      1 d\n");
+
+        // -d and -u together select nothing, as in GNU uniq
+        let config = Config { unique: true, repeated: true, ..Config::default() };
+        assert_eq!(run_uniq(text, &config).unwrap(), "");
+    }
+
+    #[test]
+    fn test_uniq_all_repeated() {
+        let text = "a\na\nb\nc\nc\nd\n";
+        let config = Config { all_repeated: Some(Delimit::None), ..Config::default() };
+        assert_eq!(run_uniq(text, &config).unwrap(), "a\na\nc\nc\n");
+
+        let config = Config { all_repeated: Some(Delimit::Prepend), ..Config::default() };
+        assert_eq!(run_uniq(text, &config).unwrap(), "\na\na\n\nc\nc\n");
+
+        let config = Config { all_repeated: Some(Delimit::Separate), ..Config::default() };
+        assert_eq!(run_uniq(text, &config).unwrap(), "a\na\n\nc\nc\n");
+    }
+
+    #[test]
+    fn test_uniq_group() {
+        let text = "a\na\nb\n";
+        let config = Config { group: Some(Group::Separate), ..Config::default() };
+        assert_eq!(run_uniq(text, &config).unwrap(), "a\na\n\nb\n");
+
+        let config = Config { group: Some(Group::Prepend), ..Config::default() };
+        assert_eq!(run_uniq(text, &config).unwrap(), "\na\na\n\nb\n");
+
+        let config = Config { group: Some(Group::Append), ..Config::default() };
+        assert_eq!(run_uniq(text, &config).unwrap(), "a\na\n\nb\n\n");
+
+        let config = Config { group: Some(Group::Both), ..Config::default() };
+        assert_eq!(run_uniq(text, &config).unwrap(), "\na\na\n\nb\n\n");
+
+        // no groups, no separators
+        assert_eq!(run_uniq("", &config).unwrap(), "");
+    }
+
+    #[test]
+    fn test_key_skip_fields() {
+        let spec = KeySpec { skip_fields: 2, ..KeySpec::default() };
+        assert_eq!(key(&spec, "10:00 INFO  started\n"), "  started");
+        assert_eq!(key(&spec, "\t10:00\tWARN"), "");
+        assert_eq!(key(&spec, "one"), "");
+    }
+
+    #[test]
+    fn test_key_skip_and_check_chars() {
+        let spec = KeySpec { skip_chars: 2, check_chars: Some(3), ..KeySpec::default() };
+        assert_eq!(key(&spec, "abcdefg\n"), "cde");
+        assert_eq!(key(&spec, "ab"), "");
+
+        // characters, not bytes, are counted
+        assert_eq!(key(&spec, "éèêëē\n"), "êëē");
+        assert_eq!(key(&spec, "日本語のテキスト"), "語のテ");
+    }
+
+    #[test]
+    fn test_key_ignore_case() {
+        let spec = KeySpec { ignore_case: true, ..KeySpec::default() };
+        assert_eq!(key(&spec, "École"), key(&spec, "éCOLE"));
+        assert_eq!(key(&spec, "STRASSE"), key(&spec, "straße"));
+        assert_eq!(key(&spec, "ΣΊΣΥΦΟΣ"), key(&spec, "σίσυφος"));
+    }
+
+    #[test]
+    fn test_uniq_key() {
+        let text = "10:00 ERROR disk full\n10:01 ERROR disk full\n10:02 error Disk Full\n10:03 ok\n";
+        let key = KeySpec { skip_fields: 1, ..KeySpec::default() };
+        let config = Config { count: true, key, ..Config::default() };
+        assert_eq!(
+            run_uniq(text, &config).unwrap(),
+            "      2 10:00 ERROR disk full\n      1 10:02 error Disk Full\n      1 10:03 ok\n"
+        );
+
+        let key = KeySpec { skip_fields: 1, ignore_case: true, ..KeySpec::default() };
+        let config = Config { count: true, key, ..Config::default() };
+        assert_eq!(
+            run_uniq(text, &config).unwrap(),
+            "      3 10:00 ERROR disk full\n      1 10:03 ok\n"
+        );
+    }
+
+    #[test]
+    fn test_uniq_global() {
+        let text = "b\na\nb\nc\na\nb\n";
+        let config = Config { global: true, ..Config::default() };
+        assert_eq!(run_uniq(text, &config).unwrap(), "b\na\nc\n");
+
+        let config = Config { global: true, count: true, ..Config::default() };
+        assert_eq!(run_uniq(text, &config).unwrap(), "      3 b\n      2 a\n      1 c\n");
+
+        let config = Config { global: true, unique: true, ..Config::default() };
+        assert_eq!(run_uniq(text, &config).unwrap(), "c\n");
+
+        let config = Config { global: true, repeated: true, ..Config::default() };
+        assert_eq!(run_uniq(text, &config).unwrap(), "b\na\n");
+    }
+
+    #[test]
+    fn test_uniq_global_digest_and_key() {
+        let text = "1 Foo\n2 bar\n3 FOO\n4 baz\n5 BAR\n";
+        let key = KeySpec { skip_fields: 1, ignore_case: true, ..KeySpec::default() };
+        let config = Config { global: true, count: true, key, ..Config::default() };
+        let expected = "      2 1 Foo\n      2 2 bar\n      1 4 baz\n";
+        assert_eq!(run_uniq(text, &config).unwrap(), expected);
+
+        let config = Config { digest: true, ..config };
+        assert_eq!(run_uniq(text, &config).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("512").unwrap(), 512);
+        assert_eq!(parse_size("4k").unwrap(), 4096);
+        assert_eq!(parse_size("64M").unwrap(), 64 << 20);
+        assert_eq!(parse_size("2G").unwrap(), 2 << 30);
+
+        for bad in ["", "0", "M", "12X", "-3", "1.5G"] {
+            let res = parse_size(bad);
+            assert!(res.is_err());
+            assert_eq!(res.unwrap_err().to_string(), bad.to_string());
+        }
+    }
+
+    #[test]
+    fn test_uniq_global_memory_limit() {
+        // a one-byte limit spills on the first line and repartitions down
+        // to the maximum depth, which must not change the result
+        let text: String = (0..500).map(|i| format!("line {}\n", (i * 7) % 97)).collect();
+        for count in [false, true] {
+            for digest in [false, true] {
+                let config = Config { global: true, count, digest, ..Config::default() };
+                let expected = run_uniq(&text, &config).unwrap();
+                let config = Config { memory_limit: Some(1), ..config };
+                assert_eq!(run_uniq(&text, &config).unwrap(), expected);
+            }
+        }
+
+        let config = Config { global: true, unique: true, memory_limit: Some(64), ..Config::default() };
+        assert_eq!(run_uniq("a\nb\na\nc\n", &config).unwrap(), "b\nc\n");
+    }
+
+    /// Yields `text` and then fails, like a disk error halfway through.
+    struct FailingReader(Cursor<String>);
+
+    impl Read for FailingReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.0.read(buf)? {
+                0 => Err(io::Error::other("read failed")),
+                n => Ok(n),
+            }
+        }
+    }
+
+    #[test]
+    fn test_uniq_global_memory_limit_cleanup() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            global: true,
+            memory_limit: Some(1),
+            temp_dir: Some(dir.path().to_string_lossy().into_owned()),
+            ..Config::default()
+        };
+        let input: Box<dyn BufRead> = Box::new(BufReader::new(FailingReader(Cursor::new("a\nb\na\n".to_string()))));
+        let res = uniq(input, Vec::new(), &config);
+        assert_eq!(res.unwrap_err().to_string(), "read failed");
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_uniq_estimate() {
+        let text = "a\nA\nb\na\n";
+        let config = Config { estimate: Some(12), ..Config::default() };
+        assert_eq!(run_uniq(text, &config).unwrap(), "3\t±1.62%\n");
+
+        let key = KeySpec { ignore_case: true, ..KeySpec::default() };
+        let config = Config { estimate: Some(12), key, ..Config::default() };
+        assert_eq!(run_uniq(text, &config).unwrap(), "2\t±1.62%\n");
+    }
+
+    #[test]
+    fn test_uniq_bloom() {
+        let text = "1 a\n2 b\n3 a\n4 c\n5 b\n";
+        let spec = BloomSpec { fp_rate: 0.001, expected_items: 100 };
+        let key = KeySpec { skip_fields: 1, ..KeySpec::default() };
+        let config = Config { bloom: Some(spec), key, ..Config::default() };
+        assert_eq!(run_uniq(text, &config).unwrap(), "1 a\n2 b\n4 c\n");
+    }
+
+    #[test]
+    fn test_uniq_histogram() {
+        // ties keep first-seen order: c before b
+        let text = "c\nb\na\nb\na\nc\na\nd";
+        let histogram = Histogram { format: Format::Text, top: None };
+        let config = Config { histogram: Some(histogram), ..Config::default() };
+        assert_eq!(
+            run_uniq(text, &config).unwrap(),
+            concat!(
+                "      3  37.50%  37.50% a\n",
+                "      2  25.00%  62.50% c\n",
+                "      2  25.00%  87.50% b\n",
+                "      1  12.50% 100.00% d\n",
+            )
+        );
+
+        // percentages stay relative to all records
+        let histogram = Histogram { format: Format::Csv, top: Some(2) };
+        let config = Config { histogram: Some(histogram), unique: true, ..Config::default() };
+        assert_eq!(
+            run_uniq("x,\"y\"\na\na\nb\n", &config).unwrap(),
+            "count,percent,cumulative_percent,line\n1,25.00,25.00,\"x,\"\"y\"\"\"\n1,25.00,50.00,b\n"
+        );
+
+        let histogram = Histogram { format: Format::Json, top: None };
+        let config = Config { histogram: Some(histogram), ..Config::default() };
+        let json: serde_json::Value = serde_json::from_str(&run_uniq("a\nb\nb\n", &config).unwrap()).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!([
+                { "count": 2, "percent": 66.67, "cumulative_percent": 66.67, "line": "b" },
+                { "count": 1, "percent": 33.33, "cumulative_percent": 100.0, "line": "a" },
+            ])
+        );
+        assert_eq!(run_uniq("", &config).unwrap(), "[]\n");
+    }
+
+    #[test]
+    fn test_key_normalize() {
+        let composed = "caf\u{e9}";
+        let decomposed = "cafe\u{301}";
+        assert_ne!(key(&KeySpec::default(), composed), key(&KeySpec::default(), decomposed));
+
+        let spec = KeySpec { normalize: Some(Normalization::Nfc), ..KeySpec::default() };
+        assert_eq!(key(&spec, decomposed), composed);
+        assert_eq!(key(&spec, composed), composed);
+        // compatibility forms only meet under NFKC
+        assert_eq!(key(&spec, "\u{fb01}le"), "\u{fb01}le");
+        let spec = KeySpec { normalize: Some(Normalization::Nfkc), ..KeySpec::default() };
+        assert_eq!(key(&spec, "\u{fb01}le"), "file");
+        assert_eq!(key(&spec, "\u{ff21}\u{ff22}"), "AB");
+
+        // -w counts normalized characters, and -i folds either form alike
+        let spec = KeySpec {
+            normalize: Some(Normalization::Nfc),
+            check_chars: Some(4),
+            ignore_case: true,
+            ..KeySpec::default()
+        };
+        assert_eq!(key(&spec, "CAFE\u{301}S"), key(&spec, "caf\u{e9}s"));
+        assert_eq!(key(&spec, "CAFE\u{301}S"), composed);
+
+        let key = KeySpec { normalize: Some(Normalization::Nfc), ..KeySpec::default() };
+        let config = Config { key, count: true, ..Config::default() };
+        let text = format!("{}\n{}\n", composed, decomposed);
+        assert_eq!(run_uniq(&text, &config).unwrap(), format!("      2 {}\n", composed));
+    }
+
+    #[test]
+    fn test_key_whitespace() {
+        let spec = KeySpec { ignore_trailing_space: true, ..KeySpec::default() };
+        assert_eq!(key(&spec, "a b \t\r\n"), "a b");
+        assert_eq!(key(&spec, "  \n"), "");
+
+        let spec = KeySpec { squeeze_space: true, ..KeySpec::default() };
+        assert_eq!(key(&spec, "a  \t b\n"), "a b");
+        assert_eq!(key(&spec, " a b "), " a b ");
+
+        // both, with -s counting the squeezed characters
+        let spec = KeySpec { ignore_trailing_space: true, squeeze_space: true, skip_chars: 2, ..KeySpec::default() };
+        assert_eq!(key(&spec, "a   b  c  "), "b c");
+    }
+
+    #[test]
+    fn test_uniq_ignore_blank_lines() {
+        let text = "a\n\na\n \t\nb\n\n";
+        assert_eq!(run_uniq(text, &Config::default()).unwrap(), "a\n\na\n \t\nb\n\n");
+        let config = Config { ignore_blank_lines: true, count: true, ..Config::default() };
+        assert_eq!(run_uniq(text, &config).unwrap(), "      2 a\n      1 b\n");
+    }
+
+    #[test]
+    fn test_key_column() {
+        let strategy = KeyStrategy::Column { index: 3, delimiter: ",".to_string() };
+        let spec = KeySpec { strategy, ..KeySpec::default() };
+        assert_eq!(key(&spec, "1,2,three,4\n"), "three");
+        assert_eq!(key(&spec, "1,2,three\n"), "three");
+        assert_eq!(key(&spec, "1,2\n"), "");
+
+        let strategy = KeyStrategy::Column { index: 1, delimiter: "::".to_string() };
+        let spec = KeySpec { strategy, ignore_case: true, ..KeySpec::default() };
+        assert_eq!(key(&spec, "Host::a::b"), "host");
+    }
+
+    #[test]
+    fn test_key_regex() {
+        let strategy = KeyStrategy::Regex(Regex::new(r"user=(\w+)").unwrap());
+        let spec = KeySpec { strategy, ..KeySpec::default() };
+        assert_eq!(key(&spec, "t=1 user=alice op=login\n"), "alice");
+        assert_eq!(key(&spec, "no user here\n"), "no user here");
+
+        let strategy = KeyStrategy::Regex(Regex::new(r"\d+").unwrap());
+        let spec = KeySpec { strategy, check_chars: Some(2), ..KeySpec::default() };
+        assert_eq!(key(&spec, "id 12345"), "12");
+    }
+
+    #[test]
+    fn test_uniq_json() {
+        let text = "{\"id\": 1, \"n\": 1}\n{\"n\": 2, \"id\": 1}\n[oops\n{\"id\": 2}\n";
+        let strategy = KeyStrategy::Json(vec![JsonPath::parse(".id").unwrap()]);
+        let config = Config { key: KeySpec { strategy, ..KeySpec::default() }, count: true, ..Config::default() };
+        assert_eq!(run_uniq(text, &config).unwrap_err().to_string(), "line 3: invalid JSON");
+
+        let skip = Config { on_invalid: OnInvalid::Skip, ..config.clone() };
+        assert_eq!(run_uniq(text, &skip).unwrap(), "      2 {\"id\": 1, \"n\": 1}\n      1 {\"id\": 2}\n");
+
+        let passthrough = Config { on_invalid: OnInvalid::Passthrough, ..config };
+        assert_eq!(
+            run_uniq(text, &passthrough).unwrap(),
+            "      2 {\"id\": 1, \"n\": 1}\n      1 [oops\n      1 {\"id\": 2}\n"
+        );
+    }
+
+    #[test]
+    fn test_uniq_keep_last() {
+        let strategy = KeyStrategy::Column { index: 1, delimiter: ",".to_string() };
+        let key = KeySpec { strategy, ..KeySpec::default() };
+        let text = "a,1\na,2\nb,1\na,3\nc,1\nb,2\n";
+
+        let config = Config { key: key.clone(), ..Config::default() };
+        assert_eq!(run_uniq(text, &config).unwrap(), "a,1\nb,1\na,3\nc,1\nb,2\n");
+
+        let config = Config { key: key.clone(), keep_last: true, ..Config::default() };
+        assert_eq!(run_uniq(text, &config).unwrap(), "a,2\nb,1\na,3\nc,1\nb,2\n");
+
+        let config = Config { key: key.clone(), global: true, ..Config::default() };
+        assert_eq!(run_uniq(text, &config).unwrap(), "a,1\nb,1\nc,1\n");
+
+        let config = Config { key, global: true, keep_last: true, count: true, ..Config::default() };
+        let expected = "      3 a,3\n      1 c,1\n      2 b,2\n";
+        assert_eq!(run_uniq(text, &config).unwrap(), expected);
+
+        let config = Config { memory_limit: Some(1), ..config };
+        assert_eq!(run_uniq(text, &config).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("250ms").unwrap(), Duration::from_millis(250));
+        assert_eq!(parse_duration("30s").unwrap(), Duration::from_secs(30));
+        assert_eq!(parse_duration("1.5").unwrap(), Duration::from_millis(1500));
+        assert_eq!(parse_duration("5m").unwrap(), Duration::from_secs(300));
+        assert_eq!(parse_duration("1h").unwrap(), Duration::from_secs(3600));
+
+        for bad in ["", "s", "0", "-1s", "10d", "1sm", "NaN"] {
+            let res = parse_duration(bad);
+            assert!(res.is_err());
+            assert_eq!(res.unwrap_err().to_string(), bad.to_string());
+        }
+    }
+
+    #[test]
+    fn test_uniq_syslog() {
+        let text = "a\na\na\nb\nc\nc\n";
+        let config = Config { syslog: true, ..Config::default() };
+        assert_eq!(
+            run_uniq(text, &config).unwrap(),
+            "a\nlast message repeated 2 times\nb\nc\nlast message repeated 1 times\n"
+        );
+
+        let config = Config { syslog: true, summary_format: "(x{count})".to_string(), ..Config::default() };
+        assert_eq!(run_uniq(text, &config).unwrap(), "a\n(x2)\nb\nc\n(x1)\n");
+    }
+
+    /// Yields each chunk with a pause before it, like a slow `tail -f`.
+    struct SlowReader(Vec<(u64, &'static str)>);
+
+    impl Read for SlowReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() {
+                return Ok(0);
+            }
+            let (pause, chunk) = self.0.remove(0);
+            thread::sleep(Duration::from_millis(pause));
+            buf[..chunk.len()].copy_from_slice(chunk.as_bytes());
+            Ok(chunk.len())
+        }
+    }
+
+    #[test]
+    fn test_uniq_syslog_window() {
+        let input = BufReader::new(SlowReader(vec![(0, "a\na\n"), (500, "a\nb\n")]));
+        let config = Config { syslog: true, ..Config::default() };
+        let mut output = Vec::new();
+        uniq_syslog_window(move |_| Ok(Box::new(input)), &mut output, &config, Duration::from_millis(100)).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "a\nlast message repeated 1 times\nlast message repeated 1 times\nb\n"
+        );
+    }
+
+    #[test]
+    fn test_uniq_window() {
+        let text = concat!(
+            "60 a\n",
+            "100 a\n",
+            "110 b\n",
+            // still within a minute of the previous a
+            "150 a\n",
+            // out of order, but within the window
+            "140 b\n",
+            "300 a\n",
+        );
+        let config = Config {
+            window: Some(Duration::from_secs(60)),
+            time_field: Some(1),
+            time_format: TimeFormat::Epoch,
+            ..Config::default()
+        };
+        assert_eq!(run_uniq(text, &config).unwrap(), "60 a\n110 b\n300 a\n");
+
+        let config = Config { count: true, ..config };
+        assert_eq!(run_uniq(text, &config).unwrap(), "      3 60 a\n      2 110 b\n      1 300 a\n");
+
+        // many keys: expired ones are dropped, without losing counts
+        let text: String = (0..5000).map(|i| format!("{} k{}\n{} k{}\n", i, i, i, i)).collect();
+        let output = run_uniq(&text, &config).unwrap();
+        assert_eq!(output.lines().count(), 5000);
+        assert!(output.lines().all(|line| line.starts_with("      2 ")));
+
+        let err = run_uniq("a b\n", &config).unwrap_err();
+        assert_eq!(err.to_string(), "line 1: invalid timestamp -- a");
+    }
+
+    #[test]
+    fn test_uniq_fuzzy() {
+        let text = "\
+timeout after 1203 ms on 0x7f3a
+timeout after 1207 ms on 0x7f3b
+timeout after 988 ms on 0x7f3c
+connection refused
+";
+        let fuzzy = Fuzzy { metric: Metric::Levenshtein, threshold: 0.9 };
+        let config = Config { count: true, fuzzy: Some(fuzzy), ..Config::default() };
+        assert_eq!(
+            run_uniq(text, &config).unwrap(),
+            "      2 timeout after 1203 ms on 0x7f3a\n      1 timeout after 988 ms on 0x7f3c\n      1 connection refused\n"
+        );
+
+        // masking makes all three timeouts identical
+        let key = KeySpec { mask: true, ..KeySpec::default() };
+        let config = Config { count: true, key, ..Config::default() };
+        assert_eq!(
+            run_uniq(text, &config).unwrap(),
+            "      3 timeout after 1203 ms on 0x7f3a\n      1 connection refused\n"
+        );
+
+        let fuzzy = Fuzzy { metric: Metric::Jaccard, threshold: 0.5 };
+        let config = Config { syslog: true, fuzzy: Some(fuzzy), ..Config::default() };
+        assert_eq!(
+            run_uniq(text, &config).unwrap(),
+            "timeout after 1203 ms on 0x7f3a\nlast message repeated 2 times\nconnection refused\n"
+        );
+    }
+
+    #[test]
+    fn test_uniq_zero_terminated() {
+        let config = Config { terminator: b'\0', count: true, ..Config::default() };
+        let res = run_uniq_bytes(b"a\nb\0a\nb\0c\0", &config).unwrap();
+        assert_eq!(res, b"      2 a\nb\0      1 c\0");
+
+        // the final record keeps its missing terminator
+        let config = Config { terminator: b'\0', ..Config::default() };
+        assert_eq!(run_uniq_bytes(b"x\0y\0y", &config).unwrap(), b"x\0y\0");
+        assert_eq!(run_uniq_bytes(b"x\0y", &config).unwrap(), b"x\0y");
+
+        let config = Config { terminator: b'\0', group: Some(Group::Append), ..Config::default() };
+        assert_eq!(run_uniq_bytes(b"x\0x\0y", &config).unwrap(), b"x\0x\0\0y\0\0");
+    }
+
+    #[test]
+    fn test_uniq_invalid_utf8() {
+        let text = b"caf\xe9\ncaf\xe9\nCAF\xc9\n\xff\xfe\n";
+        let config = Config { count: true, ..Config::default() };
+        assert_eq!(
+            run_uniq_bytes(text, &config).unwrap(),
+            b"      2 caf\xe9\n      1 CAF\xc9\n      1 \xff\xfe\n"
+        );
+
+        // invalid bytes are not folded, the valid parts are
+        let key = KeySpec { ignore_case: true, ..KeySpec::default() };
+        let config = Config { key, ..Config::default() };
+        assert_eq!(run_uniq_bytes(b"A\xff\na\xff\na\xfe\n", &config).unwrap(), b"A\xff\na\xfe\n");
+
+        let key = KeySpec { skip_chars: 1, check_chars: Some(2), ..KeySpec::default() };
+        let config = Config { global: true, key, ..Config::default() };
+        assert_eq!(run_uniq_bytes(b"1\xffab\n2\xffac\n3\xfeab\n", &config).unwrap(), b"1\xffab\n3\xfeab\n");
+    }
+
+    #[test]
+    fn test_uniq_unterminated_last_line() {
+        // written last: stays unterminated
+        let config = Config { global: true, count: true, ..Config::default() };
+        assert_eq!(run_uniq("b\na\nb", &config).unwrap(), "      2 b\n      1 a\n");
+        assert_eq!(run_uniq("b\na", &config).unwrap(), "      1 b\n      1 a");
+
+        // written before other records: gets a newline
+        let config = Config { global: true, keep_last: true, ..Config::default() };
+        assert_eq!(run_uniq("a\nb\na", &config).unwrap(), "b\na");
+        let config = Config { syslog: true, ..Config::default() };
+        assert_eq!(run_uniq("a\nb\nb", &config).unwrap(), "a\nb\nlast message repeated 1 times\n");
+    }
+}
@@ -0,0 +1,164 @@
+//! `--merge`: reads inputs that are each sorted by comparison key as one
+//! sorted stream, holding only the next record of every input in memory.
+//! Adjacent duplicates in that stream are duplicates across all inputs, so
+//! the usual adjacent engine does the rest and `-c` counts add up.
+//!
+//! Keys are compared as bytes, the order of `LC_ALL=C sort` over the key.
+//! Records of equal keys come out in input order.
+
+use super::{inputs, open, Config, MyResult};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::io::{self, BufRead, Read, Write};
+
+/// One input and its next record.
+struct Source {
+    name: String,
+    reader: Box<dyn BufRead>,
+    /// number of the line in `record`
+    line: u64,
+    record: Vec<u8>,
+}
+
+pub(crate) struct Merge<'a> {
+    config: &'a Config,
+    sources: Vec<Source>,
+    /// the key of every source's next record, smallest first
+    heap: BinaryHeap<Reverse<(Vec<u8>, usize)>>,
+    /// the record being handed out, and how much of it was consumed
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl<'a> Merge<'a> {
+    /// Opens all input files of `config` and reads their first records.
+    pub(crate) fn new(config: &'a Config) -> MyResult<Self> {
+        let mut merge = Merge { config, sources: Vec::new(), heap: BinaryHeap::new(), buf: Vec::new(), pos: 0 };
+        for name in config.files() {
+            let reader = open(name).map_err(|e| format!("{}: {}", name, e))?;
+            merge.sources.push(Source { name: name.to_string(), reader, line: 0, record: Vec::new() });
+            merge.advance(merge.sources.len() - 1, None)?;
+        }
+        Ok(merge)
+    }
+
+    /// Reads the next record of source `i` and queues it. `previous` is the
+    /// key of the record it follows, which must not be greater.
+    fn advance(&mut self, i: usize, previous: Option<&[u8]>) -> io::Result<()> {
+        let config = self.config;
+        let source = &mut self.sources[i];
+        source.record.clear();
+        if config.with_filename {
+            write!(source.record, "{:0width$x}", i, width = inputs::TAG_LEN)?;
+        }
+        let bytes_read = source
+            .reader
+            .read_until(config.terminator, &mut source.record)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", source.name, e)))?;
+        if bytes_read == 0 {
+            return Ok(());
+        }
+        source.line += 1;
+
+        let key = config.key_of(&source.record).into_owned();
+        if previous.is_some_and(|previous| key.as_slice() < previous) {
+            return Err(io::Error::other(format!("{}:{}: input is not sorted", source.name, source.line)));
+        }
+        self.heap.push(Reverse((key, i)));
+        Ok(())
+    }
+
+    /// Moves the smallest pending record into `buf`; leaves it empty once
+    /// all inputs are done.
+    fn next_record(&mut self) -> io::Result<()> {
+        self.buf.clear();
+        self.pos = 0;
+        if let Some(Reverse((key, i))) = self.heap.pop() {
+            std::mem::swap(&mut self.buf, &mut self.sources[i].record);
+            self.advance(i, Some(&key))?;
+            // a last record without a terminator gets one, so it does not
+            // run into the next record, unless no record follows at all
+            if self.buf.last() != Some(&self.config.terminator) && !self.heap.is_empty() {
+                self.buf.push(self.config.terminator);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Read for Merge<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl BufRead for Merge<'_> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos == self.buf.len() {
+            self.next_record()?;
+        }
+        Ok(&self.buf[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos += amt;
+    }
+}
+
+// -------------------- tests --------------------
+#[cfg(test)]
+mod tests {
+    use super::Merge;
+    use crate::{Config, KeySpec};
+    use std::fs;
+    use std::io::Read;
+
+    fn inputs(dir: &tempfile::TempDir, files: &[&str]) -> Vec<String> {
+        files
+            .iter()
+            .enumerate()
+            .map(|(i, text)| {
+                let path = dir.path().join(i.to_string());
+                fs::write(&path, text).unwrap();
+                path.to_str().unwrap().to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_merge() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config { inputs: inputs(&dir, &["a\nc\nc\ne", "", "b\nc\nd\n"]), ..Config::default() };
+        let mut text = String::new();
+        Merge::new(&config).unwrap().read_to_string(&mut text).unwrap();
+        assert_eq!(text, "a\nb\nc\nc\nc\nd\ne");
+
+        // a missing terminator is only added where another record follows
+        for (files, expected) in [(["b", "a\nc\n"], "a\nb\nc\n"), (["a\nb", "a\n"], "a\na\nb")] {
+            let config = Config { inputs: inputs(&dir, &files), ..Config::default() };
+            let mut text = String::new();
+            Merge::new(&config).unwrap().read_to_string(&mut text).unwrap();
+            assert_eq!(text, expected);
+        }
+
+        // sorted by key, not by line
+        let key = KeySpec { skip_fields: 1, ..KeySpec::default() };
+        let config = Config { inputs: inputs(&dir, &["2 a\n1 b\n", "3 a\n"]), key, ..Config::default() };
+        let mut text = String::new();
+        Merge::new(&config).unwrap().read_to_string(&mut text).unwrap();
+        assert_eq!(text, "2 a\n3 a\n1 b\n");
+    }
+
+    #[test]
+    fn test_merge_unsorted() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config { inputs: inputs(&dir, &["a\nb\n", "a\nc\nb\n"]), ..Config::default() };
+        let mut text = String::new();
+        let err = Merge::new(&config).unwrap().read_to_string(&mut text).unwrap_err();
+        assert_eq!(err.to_string(), format!("{}:3: input is not sorted", config.inputs[1]));
+    }
+}
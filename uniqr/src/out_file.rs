@@ -0,0 +1,207 @@
+//! Writing `OUT_FILE`: atomically by default, or appending with `--append`.
+//!
+//! A replaced file is first written to a temporary file in the same
+//! directory and renamed over the target only once everything has been
+//! written, so readers never see a half-written file and a failed run leaves
+//! the old contents alone. This also makes `uniqr FILE FILE` safe, as the
+//! input is read to the end before it is replaced.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use tempfile::NamedTempFile;
+
+/// An open output file. Write errors carry the path of the file.
+pub(crate) struct OutFile {
+    path: PathBuf,
+    kind: Kind,
+}
+
+enum Kind {
+    Replace(NamedTempFile),
+    Append(File),
+}
+
+impl OutFile {
+    /// Starts replacing `path`. A symlink is followed so the file it points
+    /// to is replaced rather than the link, and an existing file keeps its
+    /// permissions.
+    pub(crate) fn replace(path: &str) -> io::Result<Self> {
+        let path = match fs::canonicalize(path) {
+            Ok(resolved) => resolved,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => PathBuf::from(path),
+            Err(e) => return Err(e),
+        };
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+
+        let mut builder = tempfile::Builder::new();
+        builder.prefix(".uniqr");
+        #[cfg(unix)]
+        {
+            // like File::create, so the umask decides for new files
+            use std::os::unix::fs::PermissionsExt;
+            builder.permissions(fs::Permissions::from_mode(0o666));
+        }
+        let temp = builder.tempfile_in(dir).map_err(os_error)?;
+        if let Ok(metadata) = fs::metadata(&path) {
+            temp.as_file().set_permissions(metadata.permissions())?;
+        }
+        Ok(OutFile { path, kind: Kind::Replace(temp) })
+    }
+
+    /// Opens `path` for appending, creating it if needed.
+    pub(crate) fn append(path: &str) -> io::Result<Self> {
+        let file = OpenOptions::new().append(true).create(true).open(path)?;
+        Ok(OutFile { path: PathBuf::from(path), kind: Kind::Append(file) })
+    }
+
+    /// Moves the written contents into place. Dropping an `OutFile` without
+    /// committing removes the temporary file and leaves the target as it was.
+    pub(crate) fn commit(self) -> io::Result<()> {
+        match self.kind {
+            Kind::Replace(temp) => {
+                temp.as_file().sync_all().map_err(|e| annotate(&self.path, e))?;
+                temp.persist(&self.path).map_err(|e| annotate(&self.path, e.error))?;
+            }
+            Kind::Append(file) => file.sync_all().map_err(|e| annotate(&self.path, e))?,
+        }
+        Ok(())
+    }
+}
+
+impl Write for OutFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let result = match &mut self.kind {
+            Kind::Replace(temp) => temp.write(buf),
+            Kind::Append(file) => file.write(buf),
+        };
+        result.map_err(|e| annotate(&self.path, e))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let result = match &mut self.kind {
+            Kind::Replace(temp) => temp.flush(),
+            Kind::Append(file) => file.flush(),
+        };
+        result.map_err(|e| annotate(&self.path, e))
+    }
+}
+
+/// Whether `in_file` (`-` for stdin) and `out_file` are the same file.
+/// Paths that cannot be examined, such as a missing output, are not.
+pub(crate) fn same_file(in_file: &str, out_file: &str) -> bool {
+    let input = match in_file {
+        "-" => Path::new("/dev/stdin"),
+        _ => Path::new(in_file),
+    };
+    match (fs::metadata(input), fs::metadata(out_file)) {
+        #[cfg(unix)]
+        (Ok(a), Ok(b)) => {
+            use std::os::unix::fs::MetadataExt;
+            a.dev() == b.dev() && a.ino() == b.ino()
+        }
+        #[cfg(not(unix))]
+        (Ok(_), Ok(_)) => match (fs::canonicalize(input), fs::canonicalize(out_file)) {
+            (Ok(a), Ok(b)) => a == b,
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// `e` from `tempfile` without the "at path" it appends, as the user never
+/// named that temporary path.
+fn os_error(e: io::Error) -> io::Error {
+    let message = e.to_string();
+    match message.rsplit_once(" at path ") {
+        Some((cause, _)) => io::Error::new(e.kind(), cause),
+        None => e,
+    }
+}
+
+fn annotate(path: &Path, e: io::Error) -> io::Error {
+    io::Error::new(e.kind(), format!("{}: {}", path.display(), e))
+}
+
+// -------------------- tests --------------------
+#[cfg(test)]
+mod tests {
+    use super::{same_file, OutFile};
+    use std::fs;
+    use std::io::Write;
+
+    #[test]
+    fn test_replace() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.txt");
+        let name = path.to_str().unwrap();
+        fs::write(&path, "old\n").unwrap();
+
+        // nothing changes until commit, and an abandoned write leaves no trace
+        let mut out = OutFile::replace(name).unwrap();
+        out.write_all(b"new\n").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "old\n");
+        drop(out);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "old\n");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        let mut out = OutFile::replace(name).unwrap();
+        out.write_all(b"new\n").unwrap();
+        out.commit().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new\n");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_replace_keeps_permissions_and_symlinks() {
+        use std::os::unix::fs::{symlink, PermissionsExt};
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.txt");
+        let link = dir.path().join("link.txt");
+        fs::write(&path, "old\n").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+        symlink(&path, &link).unwrap();
+
+        let mut out = OutFile::replace(link.to_str().unwrap()).unwrap();
+        out.write_all(b"new\n").unwrap();
+        out.commit().unwrap();
+        assert!(fs::symlink_metadata(&link).unwrap().file_type().is_symlink());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new\n");
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o640);
+    }
+
+    #[test]
+    fn test_append() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.txt");
+        let name = path.to_str().unwrap();
+        for line in ["a\n", "b\n"] {
+            let mut out = OutFile::append(name).unwrap();
+            out.write_all(line.as_bytes()).unwrap();
+            out.commit().unwrap();
+        }
+        assert_eq!(fs::read_to_string(&path).unwrap(), "a\nb\n");
+    }
+
+    #[test]
+    fn test_same_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("in.txt");
+        let name = path.to_str().unwrap();
+        fs::write(&path, "a\n").unwrap();
+        let other = dir.path().join("other.txt");
+        fs::write(&other, "a\n").unwrap();
+        let linked = dir.path().join("linked.txt");
+        fs::hard_link(&path, &linked).unwrap();
+
+        assert!(same_file(name, name));
+        assert!(same_file(name, linked.to_str().unwrap()));
+        assert!(!same_file(name, other.to_str().unwrap()));
+        assert!(!same_file(name, dir.path().join("missing").to_str().unwrap()));
+    }
+}
@@ -0,0 +1,156 @@
+//! Probabilistic key sets for `--estimate` and `--bloom`, both fed with the
+//! same comparison keys as the exact modes.
+
+use xxhash_rust::xxh3::{xxh3_128, xxh3_64};
+
+/// HyperLogLog distinct counter with `2^precision` one-byte registers.
+#[derive(Debug, Clone)]
+pub struct HyperLogLog {
+    precision: u8,
+    registers: Vec<u8>,
+}
+
+impl HyperLogLog {
+    /// `precision` must be in `4..=18`; memory is `2^precision` bytes.
+    pub fn new(precision: u8) -> Self {
+        assert!((4..=18).contains(&precision), "precision must be in 4..=18");
+        HyperLogLog {
+            precision,
+            registers: vec![0; 1 << precision],
+        }
+    }
+
+    pub fn insert(&mut self, key: &[u8]) {
+        let hash = xxh3_64(key);
+        let index = (hash >> (64 - self.precision)) as usize;
+        // the remaining bits, with a sentinel so the rank stays bounded
+        let rest = (hash << self.precision) | (1 << (self.precision - 1));
+        let rank = rest.leading_zeros() as u8 + 1;
+        if rank > self.registers[index] {
+            self.registers[index] = rank;
+        }
+    }
+
+    /// Estimated number of distinct keys inserted so far.
+    pub fn estimate(&self) -> f64 {
+        let m = self.registers.len() as f64;
+        let alpha = match self.registers.len() {
+            16 => 0.673,
+            32 => 0.697,
+            64 => 0.709,
+            _ => 0.7213 / (1.0 + 1.079 / m),
+        };
+        let sum: f64 = self.registers.iter().map(|&r| 2f64.powi(-(r as i32))).sum();
+        let raw = alpha * m * m / sum;
+
+        // small range correction: linear counting while registers are empty
+        let zeros = self.registers.iter().filter(|&&r| r == 0).count();
+        if raw <= 2.5 * m && zeros > 0 {
+            m * (m / zeros as f64).ln()
+        } else {
+            raw
+        }
+    }
+
+    /// Relative standard error of `estimate`, `1.04 / sqrt(m)`.
+    pub fn error_bound(&self) -> f64 {
+        1.04 / (self.registers.len() as f64).sqrt()
+    }
+}
+
+/// Bloom filter sized for `expected_items` keys at a target false-positive
+/// rate, using double hashing over one 128-bit hash.
+#[derive(Debug, Clone)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    num_bits: u64,
+    num_hashes: u32,
+}
+
+impl BloomFilter {
+    pub fn new(expected_items: usize, fp_rate: f64) -> Self {
+        let n = expected_items.max(1) as f64;
+        let ln2 = std::f64::consts::LN_2;
+        let num_bits = (-n * fp_rate.ln() / (ln2 * ln2)).ceil().max(64.0) as u64;
+        let num_hashes = ((num_bits as f64 / n) * ln2).round().max(1.0) as u32;
+        BloomFilter {
+            bits: vec![0; num_bits.div_ceil(64) as usize],
+            num_bits,
+            num_hashes,
+        }
+    }
+
+    /// Adds `key` and returns whether it was (probably) present already.
+    pub fn insert(&mut self, key: &[u8]) -> bool {
+        let mut present = true;
+        for (word, mask) in self.positions(key) {
+            if self.bits[word] & mask == 0 {
+                present = false;
+                self.bits[word] |= mask;
+            }
+        }
+        present
+    }
+
+    /// Whether `key` was (probably) inserted before.
+    pub fn contains(&self, key: &[u8]) -> bool {
+        self.positions(key).all(|(word, mask)| self.bits[word] & mask != 0)
+    }
+
+    /// The word index and bit mask of each of the `num_hashes` bits of `key`.
+    fn positions(&self, key: &[u8]) -> impl Iterator<Item = (usize, u64)> + use<> {
+        let hash = xxh3_128(key);
+        let (h1, h2) = (hash as u64, (hash >> 64) as u64 | 1);
+        let num_bits = self.num_bits;
+        (0..self.num_hashes as u64).map(move |i| {
+            let bit = h1.wrapping_add(i.wrapping_mul(h2)) % num_bits;
+            ((bit / 64) as usize, 1 << (bit % 64))
+        })
+    }
+}
+
+// -------------------- tests --------------------
+#[cfg(test)]
+mod tests {
+    use super::{BloomFilter, HyperLogLog};
+
+    #[test]
+    fn test_hyperloglog() {
+        let mut hll = HyperLogLog::new(14);
+        assert_eq!(hll.estimate(), 0.0);
+
+        for i in 0..100_000 {
+            // every key twice: duplicates must not move the estimate
+            hll.insert(format!("key {}", i).as_bytes());
+            hll.insert(format!("key {}", i).as_bytes());
+        }
+        let error = (hll.estimate() - 100_000.0).abs() / 100_000.0;
+        assert!(error < 3.0 * hll.error_bound(), "error {} too large", error);
+
+        let mut small = HyperLogLog::new(4);
+        for i in 0..10 {
+            small.insert(&[i]);
+        }
+        assert!((small.estimate() - 10.0).abs() < 4.0);
+        assert_eq!(small.error_bound(), 0.26);
+    }
+
+    #[test]
+    fn test_bloom_filter() {
+        let mut bloom = BloomFilter::new(10_000, 0.01);
+        for i in 0..10_000 {
+            bloom.insert(format!("in {}", i).as_bytes());
+        }
+        // no false negatives
+        for i in 0..10_000 {
+            assert!(bloom.contains(format!("in {}", i).as_bytes()));
+        }
+        // false positives stay near the target rate
+        let false_positives = (0..10_000)
+            .filter(|i| bloom.contains(format!("out {}", i).as_bytes()))
+            .count();
+        assert!(bloom.insert(b"in 0"));
+        assert!(!bloom.insert(b"new"));
+        assert!(false_positives < 300, "{} false positives", false_positives);
+    }
+}
@@ -0,0 +1,94 @@
+//! Event timestamps for `--time-field` and `--time-format`.
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+
+/// How the `--time-field` of a line is written.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TimeFormat {
+    /// `2024-05-01T12:00:00Z`, `2024-05-01T14:00:00.5+02:00`
+    Rfc3339,
+    /// `Wed, 01 May 2024 12:00:00 +0000`
+    Rfc2822,
+    /// seconds since 1970, possibly fractional
+    Epoch,
+    /// milliseconds since 1970
+    EpochMillis,
+    /// a `strftime` pattern; times without an offset are taken as UTC
+    Custom(String),
+}
+
+impl TimeFormat {
+    pub fn parse(format: &str) -> Self {
+        match format {
+            "rfc3339" => TimeFormat::Rfc3339,
+            "rfc2822" => TimeFormat::Rfc2822,
+            "epoch" => TimeFormat::Epoch,
+            "epoch-ms" => TimeFormat::EpochMillis,
+            _ => TimeFormat::Custom(format.to_string()),
+        }
+    }
+
+    /// The time in `text`, or `None` if it is not in this format.
+    pub fn parse_time(&self, text: &str) -> Option<DateTime<Utc>> {
+        match self {
+            TimeFormat::Rfc3339 => DateTime::parse_from_rfc3339(text).ok().map(|t| t.to_utc()),
+            TimeFormat::Rfc2822 => DateTime::parse_from_rfc2822(text).ok().map(|t| t.to_utc()),
+            TimeFormat::Epoch => {
+                let seconds: f64 = text.parse().ok().filter(|s: &f64| s.is_finite())?;
+                DateTime::from_timestamp(seconds.floor() as i64, (seconds.fract() * 1e9) as u32)
+            }
+            TimeFormat::EpochMillis => DateTime::from_timestamp_millis(text.parse().ok()?),
+            TimeFormat::Custom(format) => match DateTime::parse_from_str(text, format) {
+                Ok(t) => Some(t.to_utc()),
+                Err(_) => NaiveDateTime::parse_from_str(text, format).ok().map(|t| t.and_utc()),
+            },
+        }
+    }
+
+    /// The time at the start of `text`, a record from its time field on,
+    /// and the length of the text it takes. A time may take several
+    /// blank-separated fields, as with rfc2822 or `%Y-%m-%d %H:%M:%S`; the
+    /// fewest fields that parse are taken.
+    pub fn parse_start(&self, text: &str) -> Option<(DateTime<Utc>, usize)> {
+        let is_blank = |c: char| c == ' ' || c == '\t';
+        let field_ends = text
+            .char_indices()
+            .filter(|&(i, c)| is_blank(c) && !text[..i].ends_with(is_blank))
+            .map(|(i, _)| i);
+        field_ends.chain([text.len()]).find_map(|end| Some((self.parse_time(&text[..end])?, end)))
+    }
+}
+
+// -------------------- tests --------------------
+#[cfg(test)]
+mod tests {
+    use super::TimeFormat;
+
+    fn millis(format: &str, text: &str) -> Option<i64> {
+        TimeFormat::parse(format).parse_time(text).map(|t| t.timestamp_millis())
+    }
+
+    #[test]
+    fn test_parse_time() {
+        assert_eq!(millis("rfc3339", "1970-01-01T00:01:00Z"), Some(60_000));
+        assert_eq!(millis("rfc3339", "1970-01-01T02:01:00.5+02:00"), Some(60_500));
+        assert_eq!(millis("rfc3339", "1970-01-01 00:01:00"), None);
+        assert_eq!(millis("rfc2822", "Thu, 01 Jan 1970 00:01:00 +0000"), Some(60_000));
+        assert_eq!(millis("epoch", "60.25"), Some(60_250));
+        assert_eq!(millis("epoch", "soon"), None);
+        assert_eq!(millis("epoch-ms", "60250"), Some(60_250));
+        assert_eq!(millis("%Y-%m-%d %H:%M:%S", "1970-01-01 00:01:00"), Some(60_000));
+        assert_eq!(millis("%d/%b/%Y:%H:%M:%S %z", "01/Jan/1970:01:01:00 +0100"), Some(60_000));
+    }
+
+    #[test]
+    fn test_parse_start() {
+        let start = |format: &str, text: &str| TimeFormat::parse(format).parse_start(text).map(|(t, n)| (t.timestamp(), n));
+        assert_eq!(start("rfc3339", "1970-01-01T00:01:00Z disk full"), Some((60, 20)));
+        assert_eq!(start("epoch", "60"), Some((60, 2)));
+        assert_eq!(start("rfc2822", "Thu, 01 Jan 1970 00:01:00 +0000  disk full"), Some((60, 31)));
+        assert_eq!(start("rfc2822", "Thu, 01 Jan 1970 00:01:00"), None);
+        assert_eq!(start("%Y-%m-%d %H:%M:%S", "1970-01-01 00:01:00 disk"), Some((60, 19)));
+        assert_eq!(start("epoch", "disk 60"), None);
+    }
+}
@@ -0,0 +1,627 @@
+use assert_cmd::cargo::cargo_bin_cmd;
+use std::fs;
+use tempfile::NamedTempFile;
+use uniqr::MyResult;
+
+const ONE: &str = "tests/inputs/one.txt";
+const THREE: &str = "tests/inputs/three.txt";
+
+// ---------- helper function to run commands ----------
+fn run(args: &[&str], expected_file: &str) -> MyResult<()> {
+    let expected = fs::read_to_string(expected_file)?;
+    let output = cargo_bin_cmd!().args(args).output().expect("fail");
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout).expect("invalid UTF-8");
+    assert_eq!(stdout, expected);
+
+    Ok(())
+}
+
+#[test]
+fn one() -> MyResult<()> {
+    run(&[ONE], "tests/expected/one.txt.out")
+}
+
+#[test]
+fn three() -> MyResult<()> {
+    run(&[THREE], "tests/expected/three.txt.out")
+}
+
+#[test]
+fn three_count() -> MyResult<()> {
+    run(&["-c", THREE], "tests/expected/three.txt.c.out")
+}
+
+#[test]
+fn three_stdin() -> MyResult<()> {
+    let input = fs::read_to_string(THREE)?;
+    let expected = fs::read_to_string("tests/expected/three.txt.c.out")?;
+    cargo_bin_cmd!()
+        .args(["--count", "-"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout(expected);
+    Ok(())
+}
+
+#[test]
+fn three_outfile() -> MyResult<()> {
+    let outfile = NamedTempFile::new()?;
+    let outpath = outfile.path().to_str().unwrap();
+    cargo_bin_cmd!()
+        .args([THREE, outpath])
+        .assert()
+        .success()
+        .stdout("");
+    let expected = fs::read_to_string("tests/expected/three.txt.out")?;
+    assert_eq!(fs::read_to_string(outpath)?, expected);
+    Ok(())
+}
+
+#[test]
+fn outfile_same_as_infile() -> MyResult<()> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("three.txt");
+    fs::copy(THREE, &path)?;
+    let path = path.to_str().unwrap();
+    cargo_bin_cmd!().args([path, path]).assert().success().stdout("");
+    let expected = fs::read_to_string("tests/expected/three.txt.out")?;
+    assert_eq!(fs::read_to_string(path)?, expected);
+    assert_eq!(fs::read_dir(dir.path())?.count(), 1);
+
+    // appending to the input would read our own output back
+    cargo_bin_cmd!()
+        .args(["--append", path, path])
+        .assert()
+        .failure()
+        .stderr(format!("{}: input file is output file\n", path));
+    assert_eq!(fs::read_to_string(path)?, expected);
+    Ok(())
+}
+
+#[test]
+fn outfile_append() -> MyResult<()> {
+    let outfile = NamedTempFile::new()?;
+    let outpath = outfile.path().to_str().unwrap();
+    for _ in 0..2 {
+        cargo_bin_cmd!().args(["--append", THREE, outpath]).assert().success();
+    }
+    let expected = fs::read_to_string("tests/expected/three.txt.out")?;
+    assert_eq!(fs::read_to_string(outpath)?, expected.repeat(2));
+    Ok(())
+}
+
+#[test]
+fn outfile_from_inputs() -> MyResult<()> {
+    let dir = tempfile::tempdir()?;
+    let out = dir.path().join("out.txt");
+    let out = out.to_str().unwrap();
+    cargo_bin_cmd!()
+        .args(["--merge", "-c", "--inputs", "tests/inputs/shard1.txt", "tests/inputs/shard2.txt", "-o", out])
+        .assert()
+        .success()
+        .stdout("");
+    cargo_bin_cmd!()
+        .args(["--append", "--inputs", "tests/inputs/shard1.txt", "--output", out])
+        .assert()
+        .success()
+        .stdout("");
+    assert_eq!(fs::read_to_string(out)?, "      1 a\n      3 b\n      1 c\n      2 d\na\nb\nd\n");
+    Ok(())
+}
+
+#[test]
+fn dies_bad_outfile() -> MyResult<()> {
+    cargo_bin_cmd!()
+        .args([THREE, "/nonexistent/out.txt"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::starts_with("/nonexistent/out.txt: "));
+    Ok(())
+}
+
+#[test]
+fn dies_bad_file() -> MyResult<()> {
+    cargo_bin_cmd!()
+        .arg("does-not-exist.txt")
+        .assert()
+        .failure()
+        .stderr(predicates::str::starts_with("does-not-exist.txt: "));
+    Ok(())
+}
+
+#[test]
+fn three_repeated() -> MyResult<()> {
+    run(&["-d", THREE], "tests/expected/three.txt.d.out")
+}
+
+#[test]
+fn three_unique_count() -> MyResult<()> {
+    run(&["-u", "-c", THREE], "tests/expected/three.txt.uc.out")
+}
+
+#[test]
+fn three_all_repeated_separate() -> MyResult<()> {
+    run(&["--all-repeated=separate", THREE], "tests/expected/three.txt.D.out")
+}
+
+#[test]
+fn three_group() -> MyResult<()> {
+    run(&["--group", THREE], "tests/expected/three.txt.group.out")
+}
+
+#[test]
+fn dies_group_with_count() -> MyResult<()> {
+    cargo_bin_cmd!()
+        .args(["--group", "-c", THREE])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("cannot be used with"));
+    Ok(())
+}
+
+#[test]
+fn dies_all_repeated_with_count() -> MyResult<()> {
+    cargo_bin_cmd!()
+        .args(["-D", "-c", THREE])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("cannot be used with"));
+    Ok(())
+}
+
+#[test]
+fn skip_fields_and_ignore_case() -> MyResult<()> {
+    cargo_bin_cmd!()
+        .args(["-c", "-f", "1", "-i"])
+        .write_stdin("1 Foo\n2 foo\n3 bar\n")
+        .assert()
+        .success()
+        .stdout("      2 1 Foo\n      1 3 bar\n");
+    Ok(())
+}
+
+#[test]
+fn skip_and_check_chars() -> MyResult<()> {
+    cargo_bin_cmd!()
+        .args(["-s", "1", "-w", "2"])
+        .write_stdin("xabc\nyabd\nzacd\n")
+        .assert()
+        .success()
+        .stdout("xabc\nzacd\n");
+    Ok(())
+}
+
+#[test]
+fn three_global() -> MyResult<()> {
+    run(&["--global", THREE], "tests/expected/three.txt.global.out")
+}
+
+#[test]
+fn three_global_count_digest() -> MyResult<()> {
+    run(&["--global", "--digest", "-c", THREE], "tests/expected/three.txt.global.c.out")
+}
+
+#[test]
+fn dies_digest_without_global() -> MyResult<()> {
+    cargo_bin_cmd!()
+        .args(["--digest", THREE])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("--global"));
+    Ok(())
+}
+
+#[test]
+fn three_global_memory_limit() -> MyResult<()> {
+    let dir = tempfile::tempdir()?;
+    let dirname = dir.path().to_str().unwrap();
+    run(
+        &["--global", "-c", "--memory-limit", "1", "-T", dirname, THREE],
+        "tests/expected/three.txt.global.c.out",
+    )?;
+    assert_eq!(fs::read_dir(dir.path())?.count(), 0);
+    Ok(())
+}
+
+#[test]
+fn dies_bad_memory_limit() -> MyResult<()> {
+    cargo_bin_cmd!()
+        .args(["--global", "--memory-limit", "10X", THREE])
+        .assert()
+        .failure()
+        .stderr("invalid memory limit -- 10X\n");
+    Ok(())
+}
+
+#[test]
+fn three_estimate() -> MyResult<()> {
+    cargo_bin_cmd!()
+        .args(["--estimate", "--precision", "10", THREE])
+        .assert()
+        .success()
+        .stdout("3\t±3.25%\n");
+    Ok(())
+}
+
+#[test]
+fn three_bloom() -> MyResult<()> {
+    run(&["--bloom", "--fp-rate", "0.001", THREE], "tests/expected/three.txt.global.out")
+}
+
+#[test]
+fn dies_bad_fp_rate() -> MyResult<()> {
+    cargo_bin_cmd!()
+        .args(["--bloom", "--fp-rate", "1.5", THREE])
+        .assert()
+        .failure()
+        .stderr("invalid false-positive rate -- 1.5\n");
+    Ok(())
+}
+
+#[test]
+fn key_column() -> MyResult<()> {
+    cargo_bin_cmd!()
+        .args(["--global", "--key", "2", "--delimiter", ","])
+        .write_stdin("1,alice,login\n2,bob,login\n3,alice,logout\n")
+        .assert()
+        .success()
+        .stdout("1,alice,login\n2,bob,login\n");
+    Ok(())
+}
+
+#[test]
+fn key_regex_keep_last() -> MyResult<()> {
+    cargo_bin_cmd!()
+        .args(["--global", "--keep-last", "--key-regex", r"user=(\w+)"])
+        .write_stdin("t=1 user=alice\nt=2 user=bob\nt=3 user=alice\n")
+        .assert()
+        .success()
+        .stdout("t=2 user=bob\nt=3 user=alice\n");
+    Ok(())
+}
+
+#[test]
+fn dies_bad_key_regex() -> MyResult<()> {
+    cargo_bin_cmd!()
+        .args(["--key-regex", "(", THREE])
+        .assert()
+        .failure()
+        .stderr("invalid key regex -- (\n");
+    Ok(())
+}
+
+#[test]
+fn three_syslog() -> MyResult<()> {
+    run(&["--syslog", THREE], "tests/expected/three.txt.syslog.out")
+}
+
+#[test]
+fn syslog_window_stops_on_closed_output() -> MyResult<()> {
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+    use std::time::{Duration, Instant};
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_uniqr"))
+        .args(["--syslog", "--window", "1s"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+    drop(child.stdout.take());
+    // stdin stays open, so only the failed write can end the run
+    let mut stdin = child.stdin.take().unwrap();
+    stdin.write_all(b"a\n")?;
+    stdin.flush()?;
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() > deadline {
+            child.kill()?;
+            panic!("still running after the output was closed");
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    };
+    assert!(!status.success());
+    Ok(())
+}
+
+#[test]
+fn dies_bad_window() -> MyResult<()> {
+    cargo_bin_cmd!()
+        .args(["--syslog", "--window", "soon", THREE])
+        .assert()
+        .failure()
+        .stderr("invalid window -- soon\n");
+    Ok(())
+}
+
+#[test]
+fn fuzzy_count() -> MyResult<()> {
+    cargo_bin_cmd!()
+        .args(["-c", "--fuzzy", "--similarity", "0.6", "--fuzzy-metric", "jaccard"])
+        .write_stdin("GET /a 200 12ms\nGET /a 200 15ms\nPOST /b 500 3ms\n")
+        .assert()
+        .success()
+        .stdout("      2 GET /a 200 12ms\n      1 POST /b 500 3ms\n");
+    Ok(())
+}
+
+#[test]
+fn mask_global() -> MyResult<()> {
+    cargo_bin_cmd!()
+        .args(["--global", "--mask"])
+        .write_stdin("job 1 done\njob 2 done\njob 3 failed\n")
+        .assert()
+        .success()
+        .stdout("job 1 done\njob 3 failed\n");
+    Ok(())
+}
+
+#[test]
+fn dies_bad_similarity() -> MyResult<()> {
+    cargo_bin_cmd!()
+        .args(["--fuzzy", "--similarity", "2", THREE])
+        .assert()
+        .failure()
+        .stderr("invalid similarity -- 2\n");
+    Ok(())
+}
+
+#[test]
+fn zero_terminated() -> MyResult<()> {
+    cargo_bin_cmd!()
+        .args(["-z", "-c"])
+        .write_stdin(&b"./a b\0./a b\0./c\xff"[..])
+        .assert()
+        .success()
+        .stdout(&b"      2 ./a b\0      1 ./c\xff"[..]);
+    Ok(())
+}
+
+#[test]
+fn invalid_utf8() -> MyResult<()> {
+    cargo_bin_cmd!()
+        .write_stdin(&b"\xe9t\xe9\n\xe9t\xe9\nok\n"[..])
+        .assert()
+        .success()
+        .stdout(&b"\xe9t\xe9\nok\n"[..]);
+    Ok(())
+}
+
+#[test]
+fn three_histogram() -> MyResult<()> {
+    run(&["--histogram", THREE], "tests/expected/three.txt.histogram.out")
+}
+
+#[test]
+fn three_histogram_top_csv() -> MyResult<()> {
+    run(
+        &["--histogram", "--top", "2", "--format", "csv", THREE],
+        "tests/expected/three.txt.histogram.csv.out",
+    )
+}
+
+#[test]
+fn three_histogram_json() -> MyResult<()> {
+    run(&["--histogram", "--format", "json", THREE], "tests/expected/three.txt.histogram.json.out")
+}
+
+#[test]
+fn dies_format_without_histogram() -> MyResult<()> {
+    cargo_bin_cmd!()
+        .args(["--format", "csv", THREE])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("--histogram"));
+    Ok(())
+}
+
+#[test]
+fn inputs_as_one_stream() -> MyResult<()> {
+    cargo_bin_cmd!()
+        .args(["-c", "--inputs", ONE, THREE])
+        .assert()
+        .success()
+        .stdout("      3 a\n      1 b\n      3 c\n      1 a\n");
+    Ok(())
+}
+
+#[test]
+fn inputs_per_file_with_filename() -> MyResult<()> {
+    cargo_bin_cmd!()
+        .args(["--per-file", "-H", "--inputs", ONE, THREE])
+        .assert()
+        .success()
+        .stdout(format!("{one}:a\n{three}:a\n{three}:b\n{three}:c\n{three}:a\n", one = ONE, three = THREE));
+    Ok(())
+}
+
+#[test]
+fn dies_per_file_with_histogram_or_estimate() -> MyResult<()> {
+    for option in [&["--histogram", "--format", "json"][..], &["--estimate"][..]] {
+        cargo_bin_cmd!()
+            .arg("--per-file")
+            .args(option)
+            .args(["--inputs", ONE, THREE])
+            .assert()
+            .failure()
+            .stderr(predicates::str::contains("cannot be used with"));
+    }
+    Ok(())
+}
+
+#[test]
+fn files0_from() -> MyResult<()> {
+    cargo_bin_cmd!()
+        .args(["--global", "-H", "--files0-from", "-"])
+        .write_stdin(format!("{}\0{}\0", THREE, ONE))
+        .assert()
+        .success()
+        .stdout(format!("{three}:a\n{three}:b\n{three}:c\n", three = THREE));
+    Ok(())
+}
+
+#[test]
+fn dies_files0_from_empty_name() -> MyResult<()> {
+    cargo_bin_cmd!()
+        .args(["--files0-from", "-"])
+        .write_stdin(format!("{}\0\0", THREE))
+        .assert()
+        .failure()
+        .stderr("-: invalid zero-length file name\n");
+    Ok(())
+}
+
+#[test]
+fn merge_sorted_inputs() -> MyResult<()> {
+    cargo_bin_cmd!()
+        .args(["--merge", "-c", "--inputs", "tests/inputs/shard1.txt", "tests/inputs/shard2.txt"])
+        .assert()
+        .success()
+        .stdout("      1 a\n      3 b\n      1 c\n      2 d\n");
+    Ok(())
+}
+
+#[test]
+fn merge_keeps_missing_final_newline() -> MyResult<()> {
+    cargo_bin_cmd!()
+        .args(["--merge", "--inputs", "-"])
+        .write_stdin("a\nb")
+        .assert()
+        .success()
+        .stdout("a\nb");
+    Ok(())
+}
+
+#[test]
+fn dies_merge_unsorted_input() -> MyResult<()> {
+    cargo_bin_cmd!()
+        .args(["--merge", "--inputs", "tests/inputs/shard1.txt", THREE])
+        .assert()
+        .failure()
+        .stderr(format!("{}:7: input is not sorted\n", THREE));
+    Ok(())
+}
+
+#[test]
+fn normalize_and_whitespace() -> MyResult<()> {
+    cargo_bin_cmd!()
+        .args(["-c", "--normalize=nfc", "--squeeze-space", "--ignore-trailing-space", "--ignore-blank-lines"])
+        .write_stdin("caf\u{e9}  au lait\n\ncafe\u{301} au lait \n")
+        .assert()
+        .success()
+        .stdout("      2 caf\u{e9}  au lait\n");
+    Ok(())
+}
+
+#[test]
+fn json_key() -> MyResult<()> {
+    cargo_bin_cmd!()
+        .args(["-c", "--global", "--json-key", ".user.id", "--on-invalid=skip", "tests/inputs/events.jsonl"])
+        .assert()
+        .success()
+        .stdout(concat!(
+            "      2 {\"user\": {\"id\": 1}, \"op\": \"login\"}\n",
+            "      2 {\"user\": {\"id\": 2}, \"op\": \"login\"}\n",
+        ));
+    Ok(())
+}
+
+#[test]
+fn json_canonical() -> MyResult<()> {
+    cargo_bin_cmd!()
+        .args(["-c", "--global", "--json-canonical", "--on-invalid=passthrough", "tests/inputs/events.jsonl"])
+        .assert()
+        .success()
+        .stdout(concat!(
+            "      1 {\"user\": {\"id\": 1}, \"op\": \"login\"}\n",
+            "      1 {\"op\": \"logout\", \"user\": {\"id\": 1}}\n",
+            "      2 {\"user\": {\"id\": 2}, \"op\": \"login\"}\n",
+            "      1 not json\n",
+        ));
+    Ok(())
+}
+
+#[test]
+fn dies_invalid_json() -> MyResult<()> {
+    cargo_bin_cmd!()
+        .args(["--json-key", ".op", "--json-key", ".user", "tests/inputs/events.jsonl"])
+        .assert()
+        .failure()
+        .stderr("line 4: invalid JSON\n");
+    Ok(())
+}
+
+#[test]
+fn dies_bad_json_key() -> MyResult<()> {
+    cargo_bin_cmd!()
+        .args(["--json-key", "user", "tests/inputs/events.jsonl"])
+        .assert()
+        .failure()
+        .stderr("invalid JSON key -- user\n");
+    Ok(())
+}
+
+#[test]
+fn window_by_event_time() -> MyResult<()> {
+    cargo_bin_cmd!()
+        .args(["-c", "--window", "60s", "--time-field", "1", "--time-format", "rfc3339"])
+        .arg("tests/inputs/alerts.txt")
+        .assert()
+        .success()
+        .stdout(concat!(
+            "      3 2024-05-01T12:00:00Z disk full /var\n",
+            "      1 2024-05-01T12:00:40Z cpu hot\n",
+            "      1 2024-05-01T12:02:30Z disk full /var\n",
+            "      1 2024-05-01T12:02:31Z cpu hot\n",
+        ));
+    Ok(())
+}
+
+#[test]
+fn window_keys_leave_out_the_time() -> MyResult<()> {
+    cargo_bin_cmd!()
+        .args(["--window", "60s", "--time-field", "1", "--time-format", "rfc3339", "tests/inputs/alerts.txt"])
+        .assert()
+        .success()
+        .stdout(concat!(
+            "2024-05-01T12:00:00Z disk full /var\n",
+            "2024-05-01T12:00:40Z cpu hot\n",
+            "2024-05-01T12:02:30Z disk full /var\n",
+            "2024-05-01T12:02:31Z cpu hot\n",
+        ));
+    Ok(())
+}
+
+#[test]
+fn window_by_rfc2822_event_time() -> MyResult<()> {
+    cargo_bin_cmd!()
+        .args(["-c", "--window", "60s", "--time-field", "1", "--time-format", "rfc2822"])
+        .write_stdin(concat!(
+            "Wed, 01 May 2024 12:00:00 +0000 disk full\n",
+            "Wed, 01 May 2024 14:00:30 +0200 disk full\n",
+            "Wed, 01 May 2024 12:02:00 +0000 disk full\n",
+        ))
+        .assert()
+        .success()
+        .stdout(concat!(
+            "      2 Wed, 01 May 2024 12:00:00 +0000 disk full\n",
+            "      1 Wed, 01 May 2024 12:02:00 +0000 disk full\n",
+        ));
+    Ok(())
+}
+
+#[test]
+fn dies_window_without_mode() -> MyResult<()> {
+    cargo_bin_cmd!()
+        .args(["--window", "60s", THREE])
+        .assert()
+        .failure()
+        .stderr("--window requires --syslog or --time-field\n");
+    Ok(())
+}